@@ -1,110 +1,563 @@
-use crate::sgf_parser::{SgfData, Player, Move};
+use crate::sgf_parser::{SgfData, SgfNode, Player, Move, Markup, Setup};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
 
-#[derive(Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Stone {
     Empty,
     Black,
     White,
 }
 
+impl Stone {
+    pub fn opponent(self) -> Stone {
+        match self {
+            Stone::Black => Stone::White,
+            Stone::White => Stone::Black,
+            Stone::Empty => Stone::Empty,
+        }
+    }
+}
+
+impl From<&Player> for Stone {
+    fn from(player: &Player) -> Self {
+        match player {
+            Player::Black => Stone::Black,
+            Player::White => Stone::White,
+        }
+    }
+}
+
+/// Why a move cannot be played in the current position
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+pub enum IllegalMove {
+    #[error("Point is already occupied")]
+    Occupied,
+    #[error("Move would be suicide")]
+    Suicide,
+    #[error("Move retakes a ko immediately")]
+    Ko,
+    #[error("Move repeats an earlier position (superko)")]
+    Superko,
+}
+
+/// What happened when a stone was put on the board
+#[derive(Clone, Copy, Default)]
+pub struct Placement {
+    pub captured: usize,
+    /// The stone retook a ko immediately
+    pub ko_violation: bool,
+    /// The stone's own group was left without liberties and removed
+    pub suicide: bool,
+}
+
+fn neighbors(board: &[Vec<Stone>], x: usize, y: usize) -> Vec<(usize, usize)> {
+    let mut points = Vec::with_capacity(4);
+    if x > 0 { points.push((x - 1, y)); }
+    if y > 0 { points.push((x, y - 1)); }
+    if x + 1 < board[y].len() { points.push((x + 1, y)); }
+    if y + 1 < board.len() { points.push((x, y + 1)); }
+    points
+}
+
+/// Stones of the group at (x, y) and the number of distinct liberties it has
+fn group_and_liberties(board: &[Vec<Stone>], x: usize, y: usize) -> (Vec<(usize, usize)>, usize) {
+    let color = board[y][x];
+    let mut seen = vec![vec![false; board[0].len()]; board.len()];
+    let mut group = Vec::new();
+    let mut liberties = 0;
+    let mut stack = vec![(x, y)];
+    seen[y][x] = true;
+    while let Some((cx, cy)) = stack.pop() {
+        group.push((cx, cy));
+        for (nx, ny) in neighbors(board, cx, cy) {
+            if seen[ny][nx] {
+                continue;
+            }
+            if board[ny][nx] == color {
+                seen[ny][nx] = true;
+                stack.push((nx, ny));
+            } else if board[ny][nx] == Stone::Empty {
+                seen[ny][nx] = true;
+                liberties += 1;
+            }
+        }
+    }
+    (group, liberties)
+}
+
+/// Puts `stone` at (x, y), removes opponent groups left without liberties and then the
+/// placed group itself if it has none (suicide). `ko_point` is the point a simple ko
+/// forbids retaking and the colour it forbids; it is updated for the next move.
+pub fn place_stone(board: &mut [Vec<Stone>], x: usize, y: usize, stone: Stone, ko_point: &mut Option<((usize, usize), Stone)>) -> Placement {
+    let mut placement = Placement {
+        ko_violation: *ko_point == Some(((x, y), stone)),
+        ..Default::default()
+    };
+    board[y][x] = stone;
+    let mut last_captured = None;
+    for (nx, ny) in neighbors(board, x, y) {
+        if board[ny][nx] != stone.opponent() {
+            continue;
+        }
+        let (group, liberties) = group_and_liberties(board, nx, ny);
+        if liberties == 0 {
+            placement.captured += group.len();
+            last_captured = Some((nx, ny));
+            for (gx, gy) in group {
+                board[gy][gx] = Stone::Empty;
+            }
+        }
+    }
+    let (own_group, own_liberties) = group_and_liberties(board, x, y);
+    if own_liberties == 0 {
+        placement.suicide = true;
+        for &(gx, gy) in &own_group {
+            board[gy][gx] = Stone::Empty;
+        }
+    }
+    // A lone stone that captured exactly one stone and has that point as its only liberty
+    // could be taken back at once; forbid that to the opponent for the next move.
+    // The capturer may still fill the ko.
+    *ko_point = match last_captured {
+        Some(point) if placement.captured == 1 && own_group.len() == 1 && own_liberties == 1 => Some((point, stone.opponent())),
+        _ => None,
+    };
+    placement
+}
+
+/// A single change to a game tree, holding what is needed to revert and replay it.
+/// Nodes are addressed by the child index taken at each depth from the root.
+#[derive(Debug, Clone)]
+pub enum Edit {
+    /// The node at `path` changed; `before` and `after` are its contents without children
+    Change { path: Vec<usize>, before: Box<SgfNode>, after: Box<SgfNode> },
+    /// `node` was put in at `path`, taking the `adopted` siblings from its position on as children
+    Add { path: Vec<usize>, node: Box<SgfNode>, adopted: usize },
+    /// The node at `path` was taken out, its `adopted` children spliced into its parent in its place
+    Remove { path: Vec<usize>, node: Box<SgfNode>, adopted: usize },
+}
+
+/// Edits made so far, each with the index of the game it applies to
+#[derive(Default)]
+pub struct EditHistory {
+    pub undo: Vec<(usize, Edit)>,
+    pub redo: Vec<(usize, Edit)>,
+}
+
+/// Copy of a node without its children
+fn contents_of(node: &mut SgfNode) -> SgfNode {
+    let children = std::mem::take(&mut node.children);
+    let copy = node.clone();
+    node.children = children;
+    copy
+}
+
+/// Writes `contents` to a temporary file next to `path` and renames it over `path`,
+/// so a failed write never leaves a half-written file behind
+fn write_atomically(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let (tmp, mut file) = create_new_file(|random| {
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(format!(".{:016x}.tmp", random));
+        PathBuf::from(tmp)
+    })?;
+    let result = (|| {
+        file.write_all(contents)?;
+        file.sync_all()?;
+        // Keep the permissions of the file being replaced, but not of a symlink's target
+        if let Some(metadata) = std::fs::symlink_metadata(path).ok().filter(|m| m.is_file()) {
+            std::fs::set_permissions(&tmp, metadata.permissions())?;
+        }
+        std::fs::rename(&tmp, path)?;
+        // The rename itself only lasts once the directory holding it is on disk
+        #[cfg(unix)]
+        {
+            let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+            std::fs::File::open(dir)?.sync_all()?;
+        }
+        Ok(())
+    })();
+    if result.is_err() {
+        let _ = std::fs::remove_file(&tmp);
+    }
+    result
+}
+
+/// Creates a file that didn't exist before, at the path `name` gives for a random number.
+/// `create_new` refuses to open anything already there, including a symlink planted by
+/// someone else, so another name is tried in that case.
+pub fn create_new_file(name: impl Fn(u64) -> PathBuf) -> std::io::Result<(PathBuf, std::fs::File)> {
+    use std::hash::{BuildHasher, Hasher};
+    loop {
+        // RandomState is seeded randomly, which is all the randomness std offers
+        let mut hasher = std::collections::hash_map::RandomState::new().build_hasher();
+        hasher.write_u32(std::process::id());
+        let path = name(hasher.finish());
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        match options.open(&path) {
+            Ok(file) => return Ok((path, file)),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Message for the status line, shown until the next key press
+pub enum Status {
+    Info(String),
+    Error(String),
+}
+
 pub struct GoGame {
-    pub board_size: usize,
+    pub board_width: usize,
+    pub board_height: usize,
+    /// Stones indexed as `board[y][x]`
     pub board: Vec<Vec<Stone>>,
-    pub moves: Vec<Move>,
+    /// Every game of the collection in the file, and which one is being viewed
+    pub games: Vec<SgfData>,
+    pub game_idx: usize,
+    /// Child index taken at each depth of the tree, from the root down to a leaf.
+    /// This is the line currently being viewed; `move_idx` is the depth within it.
+    pub line: Vec<usize>,
     pub move_idx: usize,
-    pub metadata: Vec<(String, String)>,
-    pub original_sgf: SgfData,
+    /// Point that cannot be played next because of a simple ko, and the colour it is forbidden to
+    pub ko_point: Option<((usize, usize), Stone)>,
+    /// Result of playing the current node's move, if it has one
+    pub last_placement: Option<Placement>,
+    /// Stones captured so far by Black and by White
+    pub black_captures: usize,
+    pub white_captures: usize,
+    /// Board after every node of the current line up to `move_idx`, for superko checks
+    pub history: Vec<Vec<Vec<Stone>>>,
+    pub edits: EditHistory,
+    /// Number of edits on the undo stack when the file was last saved; `None` once
+    /// that state can no longer be reached by undo or redo
+    pub saved_depth: Option<usize>,
+    /// Write the file after every edit instead of waiting for `:w`
+    pub autosave: bool,
+    /// Browse only: edits are refused and nothing is ever written
+    pub read_only: bool,
+    /// Copy the file to "<file>~" before it is first overwritten
+    pub backup: bool,
+    /// Whether that copy has been made for the current file
+    pub backed_up: bool,
+    pub status: Option<Status>,
     pub original_sgf_path: Option<String>,
 }
 
 impl GoGame {
-    pub fn save_to_file(&self) -> std::io::Result<()> {
-        if let Some(path) = &self.original_sgf_path {
-            if let Ok(sgf_str) = crate::sgf_parser::sgf_to_string(&self.to_sgf_data()) {
-                std::fs::write(path, sgf_str)?;
-            }
+    pub fn save_to_file(&mut self) -> std::io::Result<()> {
+        if self.read_only {
+            return Err(std::io::Error::other("opened read-only"));
+        }
+        let Some(path) = &self.original_sgf_path else {
+            return Err(std::io::Error::other("no file name, use :saveas <path>"));
+        };
+        let sgf_str = crate::sgf_parser::collection_to_string(&self.games)
+            .map_err(|_| std::io::Error::other("could not write the game as SGF"))?;
+        // Save through symlinks rather than replacing them
+        let path = std::fs::canonicalize(path).unwrap_or_else(|_| PathBuf::from(path));
+        if self.backup && !self.backed_up && path.exists() {
+            let mut backup = path.clone().into_os_string();
+            backup.push("~");
+            // Renamed into place like the file itself, which replaces whatever is at `<file>~`
+            // (even a symlink) instead of writing through it
+            write_atomically(Path::new(&backup), &std::fs::read(&path)?)?;
+            self.backed_up = true;
         }
+        write_atomically(&path, sgf_str.as_bytes())?;
+        self.saved_depth = Some(self.edits.undo.len());
         Ok(())
     }
-    pub fn to_sgf_data(&self) -> crate::sgf_parser::SgfData {
-        crate::sgf_parser::SgfData {
-            board_size: self.board_size,
-            moves: self.moves.clone(),
-            ab: self.original_sgf.ab.clone(),
-            aw: self.original_sgf.aw.clone(),
-            metadata: self.metadata.clone(),
+
+    /// Saves to `path`, which becomes the file saved to from then on
+    pub fn save_as(&mut self, path: &str) -> std::io::Result<()> {
+        let previous = self.original_sgf_path.replace(path.to_string());
+        let backed_up = std::mem::replace(&mut self.backed_up, false);
+        let result = self.save_to_file();
+        if result.is_err() {
+            self.original_sgf_path = previous;
+            self.backed_up = backed_up;
         }
+        result
     }
 
+    /// Whether there are edits that haven't been saved
+    pub fn is_dirty(&self) -> bool {
+        self.saved_depth != Some(self.edits.undo.len())
+    }
 }
 
 impl GoGame {
-    pub fn current_triangles(&self) -> &Vec<(usize, usize)> {
-        if self.move_idx > 0 && self.move_idx <= self.moves.len() {
-            &self.moves[self.move_idx - 1].triangles
-        } else {
-            static EMPTY: Vec<(usize, usize)> = Vec::new();
-            &EMPTY
+    pub fn current_markup(&self) -> &Markup {
+        &self.current_node().markup
+    }
+    /// Points dimmed at the current node, taking DD inherited from earlier nodes into account
+    pub fn dimmed_points(&self) -> Vec<(usize, usize)> {
+        self.dimmed_points_at(self.move_idx)
+    }
+
+    /// Points dimmed at depth `depth` of the current line
+    pub fn dimmed_points_at(&self, depth: usize) -> Vec<(usize, usize)> {
+        self.line_nodes()
+            .into_iter()
+            .take(depth + 1)
+            .rev()
+            .find_map(|node| node.markup.dimmed.clone())
+            .unwrap_or_default()
+    }
+}
+
+impl GoGame {
+    /// Number of nodes on the current line after the root
+    pub fn line_len(&self) -> usize {
+        self.line.len()
+    }
+
+    /// Node at `depth` on the current line; depth 0 is the root
+    pub fn node_at(&self, depth: usize) -> &SgfNode {
+        let mut node = &self.sgf().root;
+        for &child in &self.line[..depth] {
+            node = &node.children[child];
         }
+        node
     }
-    pub fn current_triangles_mut(&mut self) -> Option<&mut Vec<(usize, usize)>> {
-        if self.move_idx > 0 && self.move_idx <= self.moves.len() {
-            Some(&mut self.moves[self.move_idx - 1].triangles)
-        } else {
-            None
+
+    fn node_at_mut(&mut self, depth: usize) -> &mut SgfNode {
+        let mut node = &mut self.games[self.game_idx].root;
+        for &child in &self.line[..depth] {
+            node = &mut node.children[child];
         }
+        node
+    }
+
+    pub fn current_node(&self) -> &SgfNode {
+        self.node_at(self.move_idx)
     }
-}
 
+    fn current_node_mut(&mut self) -> &mut SgfNode {
+        self.node_at_mut(self.move_idx)
+    }
 
-impl GoGame {
-    pub fn new(sgf: SgfData, sgf_path: Option<String>) -> Self {
-        let mut board = vec![vec![Stone::Empty; sgf.board_size]; sgf.board_size];
-        for &(x, y) in &sgf.ab {
-            board[y][x] = Stone::Black;
+    /// All nodes of the current line, starting with the root
+    pub fn line_nodes(&self) -> Vec<&SgfNode> {
+        let mut nodes = Vec::with_capacity(self.line.len() + 1);
+        let mut node = &self.sgf().root;
+        nodes.push(node);
+        for &child in &self.line {
+            node = &node.children[child];
+            nodes.push(node);
         }
-        for &(x, y) in &sgf.aw {
-            board[y][x] = Stone::White;
+        nodes
+    }
+
+    /// Move played at `depth` on the current line, if that node has one
+    pub fn move_at(&self, depth: usize) -> Option<&Move> {
+        self.node_at(depth).mv.as_ref()
+    }
+
+    pub fn current_move(&self) -> Option<&Move> {
+        self.move_at(self.move_idx)
+    }
+
+    /// Cuts the line after `depth` and follows the main line (first children) down to a leaf.
+    fn extend_line_from(&mut self, depth: usize) {
+        self.line.truncate(depth);
+        let mut node = self.node_at(depth);
+        let mut tail = Vec::new();
+        while !node.children.is_empty() {
+            tail.push(0);
+            node = &node.children[0];
         }
-        let moves = sgf.moves.clone();
-        let metadata = sgf.metadata.clone();
+        self.line.extend(tail);
+    }
 
-        GoGame {
-            board_size: sgf.board_size,
-            board,
-            moves,
-            move_idx: 0,
-            metadata,
+    pub fn goto_move(&mut self, depth: usize) {
+        if depth <= self.line.len() {
+            self.move_idx = depth;
+            self.apply_moves(self.move_idx);
+        }
+    }
+
+    /// Index of the node at `depth` among its siblings, and the number of siblings (itself included)
+    pub fn variation_at(&self, depth: usize) -> (usize, usize) {
+        if depth == 0 {
+            return (0, 1);
+        }
+        (self.line[depth - 1], self.node_at(depth - 1).children.len())
+    }
+
+    /// Switches to the next (`forward`) or previous sibling of the current node. When the
+    /// current node has no siblings, the nearest ancestor that has some is switched instead.
+    pub fn switch_variation(&mut self, forward: bool) {
+        let depth = (1..=self.move_idx)
+            .rev()
+            .find(|&d| self.variation_at(d).1 > 1);
+        if let Some(depth) = depth {
+            let (idx, count) = self.variation_at(depth);
+            let new_idx = if forward { idx + 1 } else { idx.wrapping_sub(1) };
+            if new_idx < count {
+                self.line[depth - 1] = new_idx;
+                self.extend_line_from(depth);
+                self.goto_move(depth);
+            }
+        }
+    }
 
-            original_sgf: sgf,
+    /// Goes back to the nearest earlier node where the game tree branches
+    pub fn goto_branch_point(&mut self) {
+        let depth = (0..self.move_idx)
+            .rev()
+            .find(|&d| self.node_at(d).children.len() > 1);
+        if let Some(depth) = depth {
+            self.goto_move(depth);
+        }
+    }
+}
+
+impl GoGame {
+    pub fn new(games: Vec<SgfData>, sgf_path: Option<String>) -> Self {
+        let mut game = GoGame {
+            board_width: games[0].board_width,
+            board_height: games[0].board_height,
+            board: Vec::new(),
+            games,
+            game_idx: 0,
+            line: Vec::new(),
+            move_idx: 0,
+            ko_point: None,
+            last_placement: None,
+            black_captures: 0,
+            white_captures: 0,
+            history: Vec::new(),
+            edits: EditHistory::default(),
+            saved_depth: Some(0),
+            autosave: false,
+            read_only: false,
+            backup: true,
+            backed_up: false,
+            status: None,
             original_sgf_path: sgf_path,
+        };
+        game.extend_line_from(0);
+        game.apply_moves(0);
+        game
+    }
+
+    /// The game currently being viewed
+    pub fn sgf(&self) -> &SgfData {
+        &self.games[self.game_idx]
+    }
+
+    /// Switches to another game of the collection, starting at its root
+    pub fn select_game(&mut self, idx: usize) {
+        if idx >= self.games.len() {
+            return;
         }
+        self.game_idx = idx;
+        self.board_width = self.games[idx].board_width;
+        self.board_height = self.games[idx].board_height;
+        self.line.clear();
+        self.move_idx = 0;
+        self.extend_line_from(0);
+        self.apply_moves(0);
+    }
+
+    pub fn on_board(&self, x: usize, y: usize) -> bool {
+        x < self.board_width && y < self.board_height
     }
 
     pub fn reset_board(&mut self) {
-        self.board = vec![vec![Stone::Empty; self.board_size]; self.board_size];
-        for &(x, y) in &self.original_sgf.ab {
-            self.board[y][x] = Stone::Black;
-        }
-        for &(x, y) in &self.original_sgf.aw {
-            self.board[y][x] = Stone::White;
+        self.board = vec![vec![Stone::Empty; self.board_width]; self.board_height];
+    }
+
+    /// Puts a node's setup stones on the board, without any captures
+    fn apply_setup(&mut self, setup: &Setup) {
+        for (points, stone) in [(&setup.empty, Stone::Empty), (&setup.black, Stone::Black), (&setup.white, Stone::White)] {
+            for &(x, y) in points {
+                if self.on_board(x, y) {
+                    self.board[y][x] = stone;
+                }
+            }
         }
     }
 
+    /// Replays the current line from the root through the node at depth `up_to`
     pub fn apply_moves(&mut self, up_to: usize) {
         self.reset_board();
-        for i in 0..up_to {
-            if let Some(mv) = self.moves.get(i) {
-                self.board[mv.y][mv.x] = match mv.player {
-                    Player::Black => Stone::Black,
-                    Player::White => Stone::White,
-                };
+        self.ko_point = None;
+        self.last_placement = None;
+        self.black_captures = 0;
+        self.white_captures = 0;
+        self.history.clear();
+        let nodes: Vec<(Setup, Option<Move>)> = self.line_nodes()
+            .into_iter()
+            .take(up_to + 1)
+            .map(|node| (node.setup.clone(), node.mv.clone()))
+            .collect();
+        for (setup, mv) in nodes {
+            self.last_placement = None;
+            // Setup stones come before the node's move and change the position, ending any ko
+            if !setup.is_empty() {
+                self.apply_setup(&setup);
+                self.ko_point = None;
             }
+            let Some(mv) = mv else {
+                self.history.push(self.board.clone());
+                continue;
+            };
+            // Passes, and points outside the board, leave the position unchanged
+            match mv.point.filter(|&(x, y)| self.on_board(x, y)) {
+                Some((x, y)) => {
+                    let placement = place_stone(&mut self.board, x, y, Stone::from(&mv.player), &mut self.ko_point);
+                    match mv.player {
+                        Player::Black => self.black_captures += placement.captured,
+                        Player::White => self.white_captures += placement.captured,
+                    }
+                    self.last_placement = Some(placement);
+                }
+                None => self.ko_point = None,
+            }
+            self.history.push(self.board.clone());
+        }
+    }
+
+    /// Checks whether `player` may play at (x, y) in the position currently shown
+    pub fn legality(&self, x: usize, y: usize, player: &Player) -> Result<(), IllegalMove> {
+        if self.board[y][x] != Stone::Empty {
+            return Err(IllegalMove::Occupied);
+        }
+        let mut board = self.board.clone();
+        let mut ko_point = self.ko_point;
+        let placement = place_stone(&mut board, x, y, Stone::from(player), &mut ko_point);
+        if placement.suicide {
+            Err(IllegalMove::Suicide)
+        } else if placement.ko_violation {
+            Err(IllegalMove::Ko)
+        } else if self.history.contains(&board) {
+            Err(IllegalMove::Superko)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Checks whether the current node's move could be replaced by `player` playing at (x, y)
+    pub fn legality_as_replacement(&mut self, x: usize, y: usize, player: &Player) -> Result<(), IllegalMove> {
+        if self.move_idx == 0 {
+            return self.legality(x, y, player);
         }
+        self.apply_moves(self.move_idx - 1);
+        let result = self.legality(x, y, player);
+        self.apply_moves(self.move_idx);
+        result
     }
 
     pub fn next_move(&mut self) {
-        if self.move_idx < self.moves.len() {
+        if self.move_idx < self.line.len() {
             self.move_idx += 1;
             self.apply_moves(self.move_idx);
         }
@@ -118,10 +571,313 @@ impl GoGame {
     }
 
     pub fn current_player(&self) -> Player {
-        if self.move_idx < self.moves.len() {
-            self.moves[self.move_idx].player.clone()
+        if self.move_idx < self.line.len() {
+            if let Some(mv) = self.move_at(self.move_idx + 1) {
+                return mv.player.clone();
+            }
+        }
+        Player::Black
+    }
+
+    /// Inserts a node holding `mv` right after the current node. The new node takes over
+    /// the current node's children, so the rest of the line is kept below it.
+    pub fn insert_move(&mut self, mv: Move) {
+        let mut path = self.line[..self.move_idx].to_vec();
+        path.push(0);
+        let adopted = self.current_node().children.len();
+        self.perform(Edit::Add { path, node: Box::new(SgfNode::with_move(mv)), adopted });
+        self.line.insert(self.move_idx, 0);
+        self.move_idx += 1;
+        self.apply_moves(self.move_idx);
+    }
+
+    /// Removes the current node, splicing its children into its parent in its place
+    /// Plays `mv` after the current node without touching its existing continuations:
+    /// steps into the child that already has this move, or adds it as a new variation.
+    pub fn play_move(&mut self, mv: Move) {
+        let existing = self.current_node().children
+            .iter()
+            .position(|child| child.mv.as_ref().is_some_and(|m| m.player == mv.player && m.point == mv.point));
+        let idx = match existing {
+            Some(idx) => idx,
+            None => {
+                let idx = self.current_node().children.len();
+                let mut path = self.line[..self.move_idx].to_vec();
+                path.push(idx);
+                self.perform(Edit::Add { path, node: Box::new(SgfNode::with_move(mv)), adopted: 0 });
+                idx
+            }
+        };
+        self.line.truncate(self.move_idx);
+        self.line.push(idx);
+        self.move_idx += 1;
+        self.extend_line_from(self.move_idx);
+        self.apply_moves(self.move_idx);
+    }
+
+    pub fn remove_current_node(&mut self) {
+        if self.move_idx == 0 {
+            return;
+        }
+        let depth = self.move_idx;
+        let idx = self.line[depth - 1];
+        let path = self.line[..depth].to_vec();
+        let node = self.current_node_mut();
+        let adopted = node.children.len();
+        let node = contents_of(node);
+        self.perform(Edit::Remove { path, node: Box::new(node), adopted });
+        if adopted > 0 {
+            // Stay on the same continuation, which now hangs directly off the parent
+            let chosen = self.line.remove(depth);
+            self.line[depth - 1] = idx + chosen;
         } else {
-            Player::Black
+            self.extend_line_from(depth - 1);
+        }
+        self.move_idx = if depth > 1 { depth - 1 } else { depth.min(self.line.len()) };
+        self.apply_moves(self.move_idx);
+    }
+}
+
+impl GoGame {
+    /// Changes the node at `depth` on the current line through `change`, recording it for undo
+    pub fn edit_node(&mut self, depth: usize, change: impl FnOnce(&mut SgfNode)) {
+        let path = self.line[..depth].to_vec();
+        let node = self.node_at_mut(depth);
+        let before = contents_of(node);
+        change(node);
+        let after = contents_of(node);
+        // Confirming a dialog without changing anything is not an edit
+        if before.same_properties(&after) {
+            return;
+        }
+        self.record(Edit::Change { path, before: Box::new(before), after: Box::new(after) });
+        // The node's move or setup stones may have changed
+        self.apply_moves(self.move_idx);
+    }
+
+    pub fn edit_current_node(&mut self, change: impl FnOnce(&mut SgfNode)) {
+        self.edit_node(self.move_idx, change);
+    }
+
+    /// Applies a new edit to the current game and records it for undo. The caller moves
+    /// the current line to wherever suits the edit.
+    fn perform(&mut self, edit: Edit) {
+        self.apply_edit(self.game_idx, &edit, true);
+        self.record(edit);
+    }
+
+    /// Puts an edit just made on the undo stack, dropping whatever could be redone
+    fn record(&mut self, edit: Edit) {
+        if self.saved_depth.is_some_and(|depth| depth > self.edits.undo.len()) {
+            // The saved state was among the undone edits, which are now gone
+            self.saved_depth = None;
+        }
+        self.edits.undo.push((self.game_idx, edit));
+        self.edits.redo.clear();
+    }
+
+    /// Reverts the most recent edit and shows the node it touched. Returns false if there was none.
+    pub fn undo(&mut self) -> bool {
+        let Some((game, edit)) = self.edits.undo.pop() else {
+            return false;
+        };
+        let shown = self.apply_edit(game, &edit, false);
+        self.show_node(game, shown);
+        self.edits.redo.push((game, edit));
+        true
+    }
+
+    /// Replays the most recently undone edit. Returns false if there was none.
+    pub fn redo(&mut self) -> bool {
+        let Some((game, edit)) = self.edits.redo.pop() else {
+            return false;
+        };
+        let shown = self.apply_edit(game, &edit, true);
+        self.show_node(game, shown);
+        self.edits.undo.push((game, edit));
+        true
+    }
+
+    /// Applies `edit` to game `game`, or reverts it when not `forward`. Returns the path of the
+    /// node it touched: the changed or added node, or the parent of a removed one.
+    fn apply_edit(&mut self, game: usize, edit: &Edit, forward: bool) -> Vec<usize> {
+        match (edit, forward) {
+            (Edit::Change { path, before, after }, _) => {
+                let node = Self::node_at_path_mut(&mut self.games[game].root, path);
+                let children = std::mem::take(&mut node.children);
+                *node = if forward { (**after).clone() } else { (**before).clone() };
+                node.children = children;
+                path.clone()
+            }
+            (Edit::Add { path, node, adopted }, true) | (Edit::Remove { path, node, adopted }, false) => {
+                let (idx, parent_path) = path.split_last().unwrap();
+                let parent = Self::node_at_path_mut(&mut self.games[game].root, parent_path);
+                let mut node = (**node).clone();
+                node.children = parent.children.drain(*idx..*idx + *adopted).collect();
+                parent.children.insert(*idx, node);
+                path.clone()
+            }
+            (Edit::Add { path, .. }, false) | (Edit::Remove { path, .. }, true) => {
+                let (idx, parent_path) = path.split_last().unwrap();
+                let parent = Self::node_at_path_mut(&mut self.games[game].root, parent_path);
+                let removed = parent.children.remove(*idx);
+                parent.children.splice(*idx..*idx, removed.children);
+                parent_path.to_vec()
+            }
+        }
+    }
+
+    /// Makes the node at `path` of game `game` the current node
+    fn show_node(&mut self, game: usize, path: Vec<usize>) {
+        if game != self.game_idx {
+            self.select_game(game);
+        }
+        self.line = path;
+        self.move_idx = self.line.len();
+        self.extend_line_from(self.move_idx);
+        self.apply_moves(self.move_idx);
+    }
+
+    fn node_at_path_mut<'a>(root: &'a mut SgfNode, path: &[usize]) -> &'a mut SgfNode {
+        path.iter().fold(root, |node, &child| &mut node.children[child])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sgf_parser::parse_sgf;
+
+    fn game_at_end(sgf: &str) -> GoGame {
+        let mut game = GoGame::new(parse_sgf(sgf).unwrap(), None);
+        game.goto_move(game.line_len());
+        game
+    }
+
+    #[test]
+    fn captures_stones_and_groups() {
+        // White's corner stone, then White's two-stone group, run out of liberties
+        let game = game_at_end("(;SZ[5];B[ba];W[aa];B[ab];W[dd];B[bb];W[cb];B[ca];W[cc];B[db];W[de];B[bc];W[ee];B[cd];W[ed];B[dc])");
+        assert_eq!(game.board[0][0], Stone::Empty);
+        assert_eq!(game.board[1][2], Stone::Empty);
+        assert_eq!(game.board[2][2], Stone::Empty);
+        assert_eq!(game.black_captures, 3);
+        assert_eq!(game.white_captures, 0);
+    }
+
+    #[test]
+    fn rejects_suicide_and_occupied_points() {
+        let game = game_at_end("(;SZ[5]AB[ba][ab])");
+        assert!(matches!(game.legality(0, 0, &Player::White), Err(IllegalMove::Suicide)));
+        assert!(matches!(game.legality(1, 0, &Player::White), Err(IllegalMove::Occupied)));
+        assert!(game.legality(0, 0, &Player::Black).is_ok());
+    }
+
+    #[test]
+    fn ko_forbids_only_the_opponent() {
+        // Black takes the white stone at bb, leaving a ko there
+        let game = game_at_end("(;SZ[5]AB[ba][ab][bc]AW[ca][bb][db][cc];B[cb])");
+        assert_eq!(game.ko_point, Some(((1, 1), Stone::White)));
+        assert!(matches!(game.legality(1, 1, &Player::White), Err(IllegalMove::Ko)));
+        assert!(game.legality(1, 1, &Player::Black).is_ok());
+    }
+
+    #[test]
+    fn unchanged_edit_is_not_recorded() {
+        let mut game = game_at_end("(;SZ[9]PB[Lee];B[cc]C[hi])");
+        game.edit_current_node(|node| node.comment = Some(String::from("hi")));
+        game.edit_node(0, |root| root.set_property("PB", Some(String::from("Lee"))));
+        assert!(!game.is_dirty());
+        assert!(!game.undo());
+        game.edit_current_node(|node| node.comment = Some(String::from("hello")));
+        assert!(game.is_dirty());
+    }
+
+    /// Applies each step, then undoes them all and redoes them all, checking the tree and the
+    /// line shown after every step against `states`, which starts with the state before them
+    fn check_undo_redo(game: &mut GoGame, steps: &[fn(&mut GoGame)], states: &[(&str, &[usize], usize)]) {
+        let check = |game: &GoGame, (sgf, line, move_idx): (&str, &[usize], usize)| {
+            assert_eq!(crate::sgf_parser::collection_to_string(&game.games).unwrap(), format!("{}\n", sgf));
+            assert_eq!((game.line.as_slice(), game.move_idx), (line, move_idx), "line in {}", sgf);
+        };
+        check(game, states[0]);
+        for (step, &state) in steps.iter().zip(&states[1..]) {
+            step(game);
+            check(game, state);
+        }
+        // Undo and redo show the node they changed, so the line only has to lead to a leaf through it
+        let check_line = |game: &GoGame| {
+            let mut node = &game.sgf().root;
+            for &child in &game.line {
+                node = &node.children[child];
+            }
+            assert!(node.children.is_empty() && game.move_idx <= game.line.len());
+        };
+        for &(sgf, _, _) in states.iter().rev().skip(1) {
+            assert!(game.undo());
+            assert_eq!(crate::sgf_parser::collection_to_string(&game.games).unwrap(), format!("{}\n", sgf));
+            check_line(game);
         }
+        assert!(!game.undo());
+        assert!(!game.is_dirty());
+        for &(sgf, _, _) in &states[1..] {
+            assert!(game.redo());
+            assert_eq!(crate::sgf_parser::collection_to_string(&game.games).unwrap(), format!("{}\n", sgf));
+            check_line(game);
+        }
+        assert!(!game.redo());
+    }
+
+    #[test]
+    fn undoes_and_redoes_edits_at_a_branch_point() {
+        let mut game = game_at_end("(;SZ[9];B[aa](;W[bb];B[cc])(;W[dd]))");
+        game.goto_move(1);
+        check_undo_redo(&mut game, &[
+            // Inserted after B[aa], taking both variations along
+            |game| game.insert_move(Move { player: Player::White, point: Some((4, 4)) }),
+            // Removing W[bb] hands its continuation to W[ee]
+            |game| {
+                game.goto_move(3);
+                game.remove_current_node();
+            },
+            // Removing W[ee] gives B[aa] both variations back
+            |game| {
+                game.goto_move(2);
+                game.remove_current_node();
+            },
+            // A side variation goes away with nothing to hand on
+            |game| {
+                game.goto_move(2);
+                game.switch_variation(true);
+                game.remove_current_node();
+            },
+        ], &[
+            ("(;SZ[9]CA[UTF-8];B[aa](;W[bb];B[cc])(;W[dd]))", &[0, 0, 0], 1),
+            ("(;SZ[9]CA[UTF-8];B[aa];W[ee](;W[bb];B[cc])(;W[dd]))", &[0, 0, 0, 0], 2),
+            ("(;SZ[9]CA[UTF-8];B[aa];W[ee](;B[cc])(;W[dd]))", &[0, 0, 0], 2),
+            ("(;SZ[9]CA[UTF-8];B[aa](;B[cc])(;W[dd]))", &[0, 0], 1),
+            ("(;SZ[9]CA[UTF-8];B[aa];B[cc])", &[0, 0], 1),
+        ]);
+    }
+
+    #[test]
+    fn undoes_and_redoes_removing_a_node_with_variations() {
+        let mut game = game_at_end("(;SZ[9];B[aa](;W[bb];B[cc])(;W[dd]))");
+        game.goto_move(1);
+        check_undo_redo(&mut game, &[
+            |game| game.remove_current_node(),
+            // A new variation after W[bb], leaving B[cc] where it was
+            |game| game.play_move(Move { player: Player::White, point: Some((5, 5)) }),
+        ], &[
+            ("(;SZ[9]CA[UTF-8];B[aa](;W[bb];B[cc])(;W[dd]))", &[0, 0, 0], 1),
+            ("(;SZ[9]CA[UTF-8](;W[bb];B[cc])(;W[dd]))", &[0, 0], 1),
+            ("(;SZ[9]CA[UTF-8](;W[bb](;B[cc])(;W[ff]))(;W[dd]))", &[0, 1], 2),
+        ]);
+        // Playing a move that is already there only steps into it
+        game.goto_move(1);
+        let edits = game.edits.undo.len();
+        game.play_move(Move { player: Player::Black, point: Some((2, 2)) });
+        assert_eq!(game.edits.undo.len(), edits);
+        assert_eq!((game.line.as_slice(), game.move_idx), (&[0, 0][..], 2));
     }
 }
@@ -1,12 +1,14 @@
 mod sgf_parser;
 mod game;
+mod text_area;
 mod ui;
 mod ui_mode_actions;
 
 use clap::Parser;
 use std::fs;
-use crate::sgf_parser::parse_sgf;
-use crate::game::GoGame;
+use crate::sgf_parser::{decode_sgf, migrate_comment_labels, parse_sgf};
+use encoding_rs::Encoding;
+use crate::game::{GoGame, Status};
 use crate::ui::run_ui;
 
 #[derive(Parser)]
@@ -14,27 +16,68 @@ use crate::ui::run_ui;
 struct Args {
     /// Path to the SGF file
     sgf_path: String,
+    /// Character set of the file, overriding its CA property (e.g. GB2312, Shift_JIS, EUC-KR)
+    #[arg(long)]
+    encoding: Option<String>,
+    /// Save the file after every edit instead of only on :w
+    #[arg(long)]
+    autosave: bool,
+    /// Don't keep a "<file>~" copy of the file as it was before the first save
+    #[arg(long)]
+    no_backup: bool,
+    /// Only browse the file; edit hotkeys are disabled and nothing is saved
+    #[arg(long)]
+    read_only: bool,
+    /// Move labels that older versions of gocat kept in comments ("comment_label1_label2") into XL
+    #[arg(long)]
+    migrate_labels: bool,
 }
 
 fn main() {
     let args = Args::parse();
-    let sgf_content = match fs::read_to_string(&args.sgf_path) {
-        Ok(content) => content,
+    let encoding = args.encoding.as_deref().map(|label| {
+        Encoding::for_label(label.as_bytes()).unwrap_or_else(|| {
+            eprintln!("Unknown encoding: {}", label);
+            std::process::exit(1);
+        })
+    });
+    let sgf_content = match fs::read(&args.sgf_path) {
+        Ok(bytes) => decode_sgf(&bytes, encoding),
         Err(e) => {
             eprintln!("Failed to read SGF file: {}", e);
             std::process::exit(1);
         }
     };
 
-    let sgf = match parse_sgf(&sgf_content) {
-        Ok(sgf) => sgf,
+    let mut games = match parse_sgf(&sgf_content) {
+        Ok(games) => games,
         Err(e) => {
-            eprintln!("Failed to parse SGF file: {}", e);
+            eprintln!("Failed to parse SGF file {}: {}", args.sgf_path, e);
             std::process::exit(1);
         }
     };
 
-    let mut game = GoGame::new(sgf, Some(args.sgf_path.clone()));
+    let migrated = if args.migrate_labels { migrate_comment_labels(&mut games) } else { 0 };
+
+    let mut game = GoGame::new(games, Some(args.sgf_path.clone()));
+    if migrated > 0 {
+        // The file on disk still has the labels in its comments
+        game.saved_depth = None;
+    }
+    game.autosave = args.autosave;
+    game.backup = !args.no_backup;
+    game.read_only = args.read_only;
+    if !game.read_only && fs::OpenOptions::new().append(true).open(&args.sgf_path).is_err() {
+        game.read_only = true;
+        game.status = Some(Status::Info(String::from("The file is not writable, so it was opened read-only")));
+    } else if migrated > 0 && game.autosave {
+        game.status = Some(match game.save_to_file() {
+            Ok(()) => Status::Info(format!("Moved labels out of {} comments and saved", migrated)),
+            Err(e) => Status::Error(format!("Autosave failed: {}", e)),
+        });
+    } else if migrated > 0 {
+        game.status = Some(Status::Info(format!("Moved labels out of {} comments; :w to save", migrated)));
+    }
     if let Err(e) = run_ui(&mut game) {
         eprintln!("Error running UI: {}", e);
         std::process::exit(1);
@@ -1,156 +1,1190 @@
+use encoding_rs::{Encoding, UTF_8, WINDOWS_1252};
 use thiserror::Error;
 
 #[derive(Debug, Error)]
 pub enum SgfParseError {
-    #[error("Invalid SGF format")]
-    InvalidFormat,
-    #[error("Missing board size (SZ) property")]
-    MissingBoardSize,
+    #[error("{kind} at line {line}, column {column} (byte {offset})\n  {snippet}\n  {caret:>width$}", caret = "^", width = .snippet_column + 1)]
+    Syntax {
+        kind: SyntaxError,
+        offset: usize,
+        line: usize,
+        column: usize,
+        /// Text around the error on its line, and the error's position within it
+        snippet: String,
+        snippet_column: usize,
+    },
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Error)]
+pub enum SyntaxError {
+    #[error("Expected '(' to start a game tree")]
+    ExpectedGameTree,
+    #[error("Expected ';' to start a node")]
+    ExpectedNode,
+    #[error("Expected '[' to start a property value")]
+    ExpectedValue,
+    #[error("Invalid property identifier")]
+    InvalidPropertyIdent,
+    #[error("Unterminated property value")]
+    UnterminatedValue,
+    #[error("Game tree is missing its closing ')'")]
+    UnclosedGameTree,
+    #[error("Unexpected character '{0}'")]
+    UnexpectedChar(char),
+    #[error("Unexpected end of file")]
+    UnexpectedEof,
+    #[error("Invalid board size SZ[{0}], expected a size from 1 to 52 or width:height")]
+    InvalidBoardSize(String),
+}
+
+#[derive(Debug, Error)]
+pub enum InvalidGameInfo {
+    #[error("Komi must be a number, e.g. 6.5")]
+    Komi,
+    #[error("Handicap must be a whole number of stones")]
+    Handicap,
+    #[error("Result must look like B+3.5, W+R, B+T, B+F, 0 (draw), Void or ?")]
+    Result,
+    #[error("Date must look like 2024-05-01, 2024-05 or 2024, several separated by commas")]
+    Date,
+    #[error("Time limit must be a number of seconds")]
+    TimeLimit,
+}
+
+/// Game info properties of the root node that can be edited, with their names
+pub const GAME_INFO_PROPERTIES: [(&str, &str); 13] = [
+    ("PB", "Black player"),
+    ("PW", "White player"),
+    ("BR", "Black rank"),
+    ("WR", "White rank"),
+    ("KM", "Komi"),
+    ("HA", "Handicap"),
+    ("RE", "Result"),
+    ("DT", "Date"),
+    ("EV", "Event"),
+    ("RO", "Round"),
+    ("PC", "Place"),
+    ("RU", "Rules"),
+    ("TM", "Time limit"),
+];
+
+/// Largest board side the SGF coordinate system can address
+pub const MAX_BOARD_SIZE: usize = 52;
+
+/// Characters shown on either side of a syntax error
+const SNIPPET_CONTEXT: usize = 30;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Player {
     Black,
     White,
 }
 
+impl Player {
+    pub fn opponent(&self) -> Player {
+        match self {
+            Player::Black => Player::White,
+            Player::White => Player::Black,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Move {
     pub player: Player,
-    pub x: usize,
-    pub y: usize,
-    pub comment: Option<String>,
+    /// Board point (x, y) the stone is played on; `None` is a pass
+    pub point: Option<(usize, usize)>,
+}
+
+/// Kinds of board markup a node can carry, with their SGF property
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MarkupKind {
+    Triangle,  // TR
+    Circle,    // CR
+    Square,    // SQ
+    Cross,     // MA
+    Label,     // LB
+    Arrow,     // AR
+    Line,      // LN
+    Dim,       // DD
+    Selected,  // SL
+}
+
+impl MarkupKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            MarkupKind::Triangle => "Triangles",
+            MarkupKind::Circle => "Circles",
+            MarkupKind::Square => "Squares",
+            MarkupKind::Cross => "X marks",
+            MarkupKind::Label => "Labels",
+            MarkupKind::Arrow => "Arrows",
+            MarkupKind::Line => "Lines",
+            MarkupKind::Dim => "Dimmed points",
+            MarkupKind::Selected => "Selected points",
+        }
+    }
+}
+
+/// Start and end point of an arrow or line
+pub type PointPair = ((usize, usize), (usize, usize));
+
+/// Markup drawn on the board at a node
+#[derive(Debug, Clone, Default)]
+pub struct Markup {
     pub triangles: Vec<(usize, usize)>,
+    pub circles: Vec<(usize, usize)>,
+    pub squares: Vec<(usize, usize)>,
+    pub crosses: Vec<(usize, usize)>,
+    pub selected: Vec<(usize, usize)>,
+    /// DD stays in effect on later nodes until one sets it again; `Some(vec![])` undims everything
+    pub dimmed: Option<Vec<(usize, usize)>>,
+    pub labels: Vec<((usize, usize), String)>,
+    pub arrows: Vec<PointPair>,
+    pub lines: Vec<PointPair>,
+}
+
+impl Markup {
+    /// Point list of the kinds that mark single points; `None` for labels, arrows and lines
+    pub fn points(&self, kind: MarkupKind) -> Option<&Vec<(usize, usize)>> {
+        match kind {
+            MarkupKind::Triangle => Some(&self.triangles),
+            MarkupKind::Circle => Some(&self.circles),
+            MarkupKind::Square => Some(&self.squares),
+            MarkupKind::Cross => Some(&self.crosses),
+            MarkupKind::Selected => Some(&self.selected),
+            MarkupKind::Dim => self.dimmed.as_ref(),
+            MarkupKind::Label | MarkupKind::Arrow | MarkupKind::Line => None,
+        }
+    }
+}
+
+/// What a setup property puts on its points
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SetupKind {
+    Black,  // AB
+    White,  // AW
+    Empty,  // AE
+}
+
+impl SetupKind {
+    /// Every kind, in the order their properties are written
+    pub const ALL: [SetupKind; 3] = [SetupKind::Black, SetupKind::White, SetupKind::Empty];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            SetupKind::Black => "Black stones",
+            SetupKind::White => "White stones",
+            SetupKind::Empty => "Cleared points",
+        }
+    }
+}
+
+/// Stones added to or removed from the board at a node, whoever's turn it is
+#[derive(Debug, Clone, Default)]
+pub struct Setup {
+    pub black: Vec<(usize, usize)>,
+    pub white: Vec<(usize, usize)>,
+    pub empty: Vec<(usize, usize)>,
+}
+
+impl Setup {
+    pub fn is_empty(&self) -> bool {
+        self.black.is_empty() && self.white.is_empty() && self.empty.is_empty()
+    }
+
+    pub fn points(&self, kind: SetupKind) -> &Vec<(usize, usize)> {
+        match kind {
+            SetupKind::Black => &self.black,
+            SetupKind::White => &self.white,
+            SetupKind::Empty => &self.empty,
+        }
+    }
+
+    /// Replaces the points of `kind`. A point may only appear once among AB, AW and AE,
+    /// so the new points are dropped from the other two lists.
+    pub fn set_points(&mut self, kind: SetupKind, points: Vec<(usize, usize)>) {
+        for list in [&mut self.black, &mut self.white, &mut self.empty] {
+            list.retain(|p| !points.contains(p));
+        }
+        match kind {
+            SetupKind::Black => self.black = points,
+            SetupKind::White => self.white = points,
+            SetupKind::Empty => self.empty = points,
+        }
+    }
+}
+
+/// Move annotations, judging the move played at a node
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MoveAnnotation {
+    Bad,          // BM
+    Doubtful,     // DO
+    Interesting,  // IT
+    Tesuji,       // TE
+}
+
+impl MoveAnnotation {
+    /// Conventional badge, e.g. "??" for a very bad move
+    pub fn badge(self, emphasized: bool) -> &'static str {
+        match (self, emphasized) {
+            (MoveAnnotation::Bad, false) => "?",
+            (MoveAnnotation::Bad, true) => "??",
+            (MoveAnnotation::Doubtful, _) => "?!",
+            (MoveAnnotation::Interesting, _) => "!?",
+            (MoveAnnotation::Tesuji, false) => "!",
+            (MoveAnnotation::Tesuji, true) => "!!",
+        }
+    }
+}
+
+/// Position annotations, judging the position reached at a node
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PositionAnnotation {
+    GoodForBlack,  // GB
+    GoodForWhite,  // GW
+    Even,          // DM
+    Unclear,       // UC
+}
+
+impl PositionAnnotation {
+    pub fn name(self) -> &'static str {
+        match self {
+            PositionAnnotation::GoodForBlack => "Good for Black",
+            PositionAnnotation::GoodForWhite => "Good for White",
+            PositionAnnotation::Even => "Even",
+            PositionAnnotation::Unclear => "Unclear",
+        }
+    }
+}
+
+/// Annotation properties of a node. The flag paired with an annotation is set
+/// when the SGF value is 2, i.e. the annotation is emphasized ("very bad move").
+#[derive(Debug, Clone, Default)]
+pub struct Annotations {
+    pub move_annotation: Option<(MoveAnnotation, bool)>,
+    pub position: Option<(PositionAnnotation, bool)>,
+    /// HO: the node is a hotspot, emphasized if it's very important
+    pub hotspot: Option<bool>,
+    /// V: estimated score of the position, positive is good for Black
+    pub value: Option<f64>,
+}
+
+/// A single node of the game tree. The first child continues the main line,
+/// any further children are side variations branching off at this node.
+#[derive(Debug, Clone, Default)]
+pub struct SgfNode {
+    pub mv: Option<Move>,
+    pub setup: Setup,
+    pub comment: Option<String>,
+    pub markup: Markup,
+    pub annotations: Annotations,
+    /// Free-form labels of the node such as "attack" or "ko", kept in the private XL property
+    pub move_labels: Vec<String>,
+    /// Every other property of the node with all its values, in file order.
+    /// For the root node this includes the game info such as PB, PW and KM.
+    pub properties: Vec<(String, Vec<String>)>,
+    pub children: Vec<SgfNode>,
+}
+
+impl SgfNode {
+    pub fn with_move(mv: Move) -> Self {
+        SgfNode { mv: Some(mv), ..Default::default() }
+    }
+
+    /// First value of one of the node's other properties, e.g. "PB" on the root
+    pub fn property(&self, key: &str) -> Option<&str> {
+        self.properties.iter().find(|(k, _)| k == key)?.1.first().map(String::as_str)
+    }
+
+    /// Replaces the node's `key` property with a single value, or removes it for `None`
+    pub fn set_property(&mut self, key: &str, value: Option<String>) {
+        match value {
+            Some(value) => match self.properties.iter_mut().find(|(k, _)| k == key) {
+                Some((_, existing)) => *existing = vec![value],
+                None => self.properties.push((key.to_string(), vec![value])),
+            },
+            None => self.properties.retain(|(k, _)| k != key),
+        }
+    }
+
+    /// Whether both nodes are written out with the same properties, children aside
+    pub fn same_properties(&self, other: &SgfNode) -> bool {
+        let (mut mine, mut theirs) = (String::new(), String::new());
+        write_node_properties(&mut mine, self);
+        write_node_properties(&mut theirs, other);
+        mine == theirs
+    }
+
+    /// Adds values to the node's `key` property, creating it if the node doesn't have it yet
+    fn push_property(&mut self, key: &str, values: &[String]) {
+        match self.properties.iter_mut().find(|(k, _)| k == key) {
+            Some((_, existing)) => existing.extend_from_slice(values),
+            None => self.properties.push((key.to_string(), values.to_vec())),
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct SgfData {
-    pub board_size: usize,
-    pub moves: Vec<Move>,
-    pub ab: Vec<(usize, usize)>, // Add Black stones
-    pub aw: Vec<(usize, usize)>, // Add White stones
-    pub metadata: Vec<(String, String)>,
+    pub board_width: usize,
+    pub board_height: usize,
+    /// Root node of the game tree; the board size above is kept out of it
+    pub root: SgfNode,
 }
 
-pub fn sgf_to_string(sgf: &SgfData) -> Result<String, ()> {
-    let mut out = String::new();
-    out.push_str(&format!("(;SZ[{}]", sgf.board_size));
-    for (k, v) in &sgf.metadata {
-        out.push_str(&format!("{}[{}]", k, v));
+/// SGF letter for a coordinate: 'a'..'z' for 0-25, then 'A'..'Z' for 26-51
+pub fn coord_to_char(v: usize) -> char {
+    if v < 26 {
+        (b'a' + v as u8) as char
+    } else {
+        (b'A' + (v - 26) as u8) as char
+    }
+}
+
+pub fn char_to_coord(c: char) -> Option<usize> {
+    match c {
+        'a'..='z' => Some(c as usize - 'a' as usize),
+        'A'..='Z' => Some(c as usize - 'A' as usize + 26),
+        _ => None,
+    }
+}
+
+pub fn xy_to_sgf_coords(x: usize, y: usize) -> String {
+    format!("{}{}", coord_to_char(x), coord_to_char(y))
+}
+
+/// Checks the value of a game info property against the format SGF gives it.
+/// Free-text properties such as PB or EV accept anything.
+pub fn validate_game_info(key: &str, value: &str) -> Result<(), InvalidGameInfo> {
+    let value = value.trim();
+    let is_real = |v: &str| v.parse::<f64>().is_ok_and(f64::is_finite);
+    match key {
+        "KM" if !is_real(value) => Err(InvalidGameInfo::Komi),
+        "HA" if value.parse::<u32>().is_err() => Err(InvalidGameInfo::Handicap),
+        "TM" if !is_real(value) => Err(InvalidGameInfo::TimeLimit),
+        "RE" if !is_valid_result(value) => Err(InvalidGameInfo::Result),
+        "DT" if !is_valid_date(value) => Err(InvalidGameInfo::Date),
+        _ => Ok(()),
+    }
+}
+
+/// RE: "0" or "Draw", "Void", "?", or the winner followed by "+" and a score,
+/// R/Resign, T/Time or F/Forfeit, or nothing when the margin is unknown
+fn is_valid_result(value: &str) -> bool {
+    if matches!(value, "0" | "Draw" | "Void" | "?") {
+        return true;
+    }
+    let Some(how) = value.strip_prefix("B+").or_else(|| value.strip_prefix("W+")) else {
+        return false;
+    };
+    matches!(how, "" | "R" | "Resign" | "T" | "Time" | "F" | "Forfeit")
+        || how.parse::<f64>().is_ok_and(|score| score.is_finite() && score > 0.0)
+}
+
+/// DT: comma-separated dates in ISO format "YYYY-MM-DD", "YYYY-MM" or "YYYY".
+/// After the first date, later ones may leave out what they share with it ("MM-DD" or "DD").
+fn is_valid_date(value: &str) -> bool {
+    let number = |part: &str, len: usize, range: std::ops::RangeInclusive<u32>| {
+        part.len() == len && part.bytes().all(|b| b.is_ascii_digit()) && part.parse().is_ok_and(|n| range.contains(&n))
+    };
+    value.split(',').map(str::trim).enumerate().all(|(i, date)| {
+        let parts: Vec<&str> = date.split('-').collect();
+        match parts.as_slice() {
+            [year] => number(year, 4, 0..=9999) || (i > 0 && number(year, 2, 1..=31)),
+            [year, month] if year.len() == 4 => number(year, 4, 0..=9999) && number(month, 2, 1..=12),
+            [month, day] => i > 0 && number(month, 2, 1..=12) && number(day, 2, 1..=31),
+            [year, month, day] => number(year, 4, 0..=9999) && number(month, 2, 1..=12) && number(day, 2, 1..=31),
+            _ => false,
+        }
+    })
+}
+
+/// Escapes a property value for writing: backslashes and closing brackets get a backslash
+fn escape_text(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        if c == '\\' || c == ']' {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+/// Reverses `escape_text` on a raw property value. A backslash followed by a line break
+/// is a soft line break and is removed together with the break.
+fn unescape_text(raw: &str) -> String {
+    let mut out = String::with_capacity(raw.len());
+    let mut chars = raw.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some(first @ ('\n' | '\r')) => {
+                // "\r\n" and "\n\r" count as a single line break
+                let second = if first == '\n' { '\r' } else { '\n' };
+                chars.next_if_eq(&second);
+            }
+            Some(escaped) => out.push(escaped),
+            None => {}
+        }
     }
-    for &(x, y) in &sgf.ab {
-        let coord = format!("{}{}", (b'a' + x as u8) as char, (b'a' + y as u8) as char);
-        out.push_str(&format!("AB[{}]", coord));
+    out
+}
+
+fn push_points(out: &mut String, tag: &str, points: &[(usize, usize)]) {
+    if points.is_empty() {
+        return;
     }
-    for &(x, y) in &sgf.aw {
-        let coord = format!("{}{}", (b'a' + x as u8) as char, (b'a' + y as u8) as char);
-        out.push_str(&format!("AW[{}]", coord));
+    out.push_str(tag);
+    for &(x, y) in points {
+        out.push_str(&format!("[{}]", xy_to_sgf_coords(x, y)));
     }
+}
 
-    for mv in &sgf.moves {
-        let coord = format!("{}{}", (b'a' + mv.x as u8) as char, (b'a' + mv.y as u8) as char);
+fn push_point_pairs(out: &mut String, tag: &str, pairs: &[PointPair]) {
+    if pairs.is_empty() {
+        return;
+    }
+    out.push_str(tag);
+    for &((x1, y1), (x2, y2)) in pairs {
+        out.push_str(&format!("[{}:{}]", xy_to_sgf_coords(x1, y1), xy_to_sgf_coords(x2, y2)));
+    }
+}
+
+fn write_annotations(out: &mut String, annotations: &Annotations) {
+    let double = |emphasized: bool| if emphasized { "2" } else { "1" };
+    if let Some((annotation, emphasized)) = annotations.move_annotation {
+        match annotation {
+            MoveAnnotation::Bad => out.push_str(&format!("BM[{}]", double(emphasized))),
+            MoveAnnotation::Tesuji => out.push_str(&format!("TE[{}]", double(emphasized))),
+            MoveAnnotation::Doubtful => out.push_str("DO[]"),
+            MoveAnnotation::Interesting => out.push_str("IT[]"),
+        }
+    }
+    if let Some((annotation, emphasized)) = annotations.position {
+        let tag = match annotation {
+            PositionAnnotation::GoodForBlack => "GB",
+            PositionAnnotation::GoodForWhite => "GW",
+            PositionAnnotation::Even => "DM",
+            PositionAnnotation::Unclear => "UC",
+        };
+        out.push_str(&format!("{}[{}]", tag, double(emphasized)));
+    }
+    if let Some(emphasized) = annotations.hotspot {
+        out.push_str(&format!("HO[{}]", double(emphasized)));
+    }
+    if let Some(value) = annotations.value {
+        out.push_str(&format!("V[{}]", value));
+    }
+}
+
+fn write_node_properties(out: &mut String, node: &SgfNode) {
+    push_points(out, "AB", &node.setup.black);
+    push_points(out, "AW", &node.setup.white);
+    push_points(out, "AE", &node.setup.empty);
+    if let Some(mv) = &node.mv {
         let tag = match mv.player {
             Player::Black => "B",
             Player::White => "W",
         };
-        out.push_str(&format!(";{}[{}]", tag, coord));
-        for &(x, y) in &mv.triangles {
-            let coord = format!("{}{}", (b'a' + x as u8) as char, (b'a' + y as u8) as char);
-            out.push_str(&format!("TR[{}]", coord));
+        let coord = mv.point.map(|(x, y)| xy_to_sgf_coords(x, y)).unwrap_or_default();
+        out.push_str(&format!("{}[{}]", tag, coord));
+    }
+    let markup = &node.markup;
+    push_points(out, "TR", &markup.triangles);
+    push_points(out, "CR", &markup.circles);
+    push_points(out, "SQ", &markup.squares);
+    push_points(out, "MA", &markup.crosses);
+    push_points(out, "SL", &markup.selected);
+    match &markup.dimmed {
+        Some(points) if points.is_empty() => out.push_str("DD[]"),
+        Some(points) => push_points(out, "DD", points),
+        None => {}
+    }
+    if !markup.labels.is_empty() {
+        out.push_str("LB");
+        for &((x, y), ref text) in &markup.labels {
+            out.push_str(&format!("[{}:{}]", xy_to_sgf_coords(x, y), escape_text(text)));
         }
-        if let Some(comment) = &mv.comment {
-            out.push_str(&format!("C[{}]", comment.replace("]", "\\]")));
+    }
+    push_point_pairs(out, "AR", &markup.arrows);
+    push_point_pairs(out, "LN", &markup.lines);
+    write_annotations(out, &node.annotations);
+    if let Some(comment) = &node.comment {
+        out.push_str(&format!("C[{}]", escape_text(comment)));
+    }
+    if !node.move_labels.is_empty() {
+        out.push_str("XL");
+        for label in &node.move_labels {
+            out.push_str(&format!("[{}]", escape_text(label)));
+        }
+    }
+    for (key, values) in &node.properties {
+        out.push_str(key);
+        for value in values {
+            out.push_str(&format!("[{}]", escape_text(value)));
         }
     }
-    out.push_str(")");
+}
+
+/// Writes the descendants of `node`. Straight runs of single children are written
+/// as one sequence; every branch point opens a parenthesised subtree per child.
+fn write_children(out: &mut String, mut node: &SgfNode) {
+    while node.children.len() == 1 {
+        node = &node.children[0];
+        out.push(';');
+        write_node_properties(out, node);
+    }
+    for child in &node.children {
+        out.push_str("(;");
+        write_node_properties(out, child);
+        write_children(out, child);
+        out.push(')');
+    }
+}
+
+pub fn sgf_to_string(sgf: &SgfData) -> Result<String, ()> {
+    let mut out = String::new();
+    if sgf.board_width == sgf.board_height {
+        out.push_str(&format!("(;SZ[{}]", sgf.board_width));
+    } else {
+        out.push_str(&format!("(;SZ[{}:{}]", sgf.board_width, sgf.board_height));
+    }
+    // Whatever the file was read as, it is written back as UTF-8
+    out.push_str("CA[UTF-8]");
+    write_node_properties(&mut out, &sgf.root);
+    write_children(&mut out, &sgf.root);
+    out.push(')');
     Ok(out)
 }
 
-pub fn parse_sgf(sgf: &str) -> Result<SgfData, SgfParseError> {
-    // Enhanced SGF parser that handles multiple bracketed values for AB/AW properties
-    let mut board_size = None;
-    let mut moves = Vec::new();
-    let mut ab = Vec::new();
-    let mut aw = Vec::new();
-    let mut metadata = Vec::new();
-
-    // First, find all property-value pairs using a more comprehensive approach
-    let prop_re = regex::Regex::new(r"([A-Z]+)((?:\[[^\]]*\])+)").unwrap();
-    let bracket_re = regex::Regex::new(r"\[([^\]]*)\]").unwrap();
-    
-    for cap in prop_re.captures_iter(sgf) {
-        let key = &cap[1];
-        let values_str = &cap[2]; // This captures all bracketed values like "[dp][pd][pp]"
-        
-        // Extract all individual bracketed values
-        let values: Vec<&str> = bracket_re.captures_iter(values_str)
-            .map(|m| m.get(1).unwrap().as_str())
-            .collect();
-        
+struct Parser<'a> {
+    src: &'a str,
+    pos: usize,
+    board_size: Option<(usize, usize)>,
+}
+
+impl<'a> Parser<'a> {
+    fn error(&self, kind: SyntaxError, offset: usize) -> SgfParseError {
+        let line_start = self.src[..offset].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = self.src[offset..].find('\n').map_or(self.src.len(), |i| offset + i);
+        let line = self.src[..offset].matches('\n').count() + 1;
+        let column = self.src[line_start..offset].chars().count() + 1;
+        // Keep the snippet short on very long lines, such as files written on a single line
+        let snippet_start = self.src[line_start..offset]
+            .char_indices()
+            .rev()
+            .nth(SNIPPET_CONTEXT)
+            .map_or(line_start, |(i, _)| line_start + i);
+        let snippet_end = self.src[offset..line_end]
+            .char_indices()
+            .nth(SNIPPET_CONTEXT)
+            .map_or(line_end, |(i, _)| offset + i);
+        SgfParseError::Syntax {
+            kind,
+            offset,
+            line,
+            column,
+            snippet: self.src[snippet_start..snippet_end].trim_end_matches('\r').to_string(),
+            snippet_column: self.src[snippet_start..offset].chars().count(),
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.src.as_bytes().get(self.pos).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(|b| b.is_ascii_whitespace()) {
+            self.pos += 1;
+        }
+    }
+
+    /// Consumes `expected` after optional whitespace, or fails with `kind`
+    fn expect(&mut self, expected: u8, kind: SyntaxError) -> Result<(), SgfParseError> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some(b) if b == expected => {
+                self.pos += 1;
+                Ok(())
+            }
+            Some(_) => Err(self.error(kind, self.pos)),
+            None => Err(self.error(SyntaxError::UnexpectedEof, self.pos)),
+        }
+    }
+
+    /// GameTree = "(" Sequence { GameTree } ")"
+    /// Returns the first node of the sequence with the rest chained below it.
+    fn game_tree(&mut self) -> Result<SgfNode, SgfParseError> {
+        self.expect(b'(', SyntaxError::ExpectedGameTree)?;
+        self.expect(b';', SyntaxError::ExpectedNode)?;
+        let mut sequence = vec![self.node()?];
+        let mut variations = Vec::new();
+        loop {
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b';') if variations.is_empty() => {
+                    self.pos += 1;
+                    sequence.push(self.node()?);
+                }
+                Some(b'(') => variations.push(self.game_tree()?),
+                Some(b')') => {
+                    self.pos += 1;
+                    break;
+                }
+                Some(_) => {
+                    let c = self.src[self.pos..].chars().next().unwrap();
+                    return Err(self.error(SyntaxError::UnexpectedChar(c), self.pos));
+                }
+                None => return Err(self.error(SyntaxError::UnclosedGameTree, self.pos)),
+            }
+        }
+        let mut node = sequence.pop().unwrap();
+        node.children = variations;
+        while let Some(mut parent) = sequence.pop() {
+            parent.children.push(node);
+            node = parent;
+        }
+        Ok(node)
+    }
+
+    /// Node = ";" { Property }, with the semicolon already consumed
+    fn node(&mut self) -> Result<SgfNode, SgfParseError> {
+        let mut node = SgfNode::default();
+        loop {
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b) if b.is_ascii_alphabetic() => {
+                    let start = self.pos;
+                    let (key, values) = self.property()?;
+                    self.apply_property(&mut node, &key, &values).map_err(|kind| self.error(kind, start))?;
+                }
+                _ => return Ok(node),
+            }
+        }
+    }
+
+    /// Property = PropIdent PropValue { PropValue }
+    fn property(&mut self) -> Result<(String, Vec<String>), SgfParseError> {
+        let start = self.pos;
+        while self.peek().is_some_and(|b| b.is_ascii_alphabetic()) {
+            self.pos += 1;
+        }
+        // Old FF[3] files may spell identifiers with extra lowercase letters, e.g. "GaMe"
+        let key: String = self.src[start..self.pos].chars().filter(|c| c.is_ascii_uppercase()).collect();
+        if key.is_empty() {
+            return Err(self.error(SyntaxError::InvalidPropertyIdent, start));
+        }
+        self.expect(b'[', SyntaxError::ExpectedValue)?;
+        let mut values = vec![self.value()?];
+        loop {
+            self.skip_whitespace();
+            if self.peek() != Some(b'[') {
+                break;
+            }
+            self.pos += 1;
+            values.push(self.value()?);
+        }
+        Ok((key, values))
+    }
+
+    /// Reads a property value up to the closing bracket, with the opening one already consumed
+    fn value(&mut self) -> Result<String, SgfParseError> {
+        let start = self.pos;
+        loop {
+            match self.peek() {
+                Some(b']') => break,
+                // Skip whatever is escaped; multi-byte characters can never be ']' or '\'
+                Some(b'\\') => self.pos += 2,
+                Some(_) => self.pos += 1,
+                None => return Err(self.error(SyntaxError::UnterminatedValue, start - 1)),
+            }
+        }
+        let raw = &self.src[start..self.pos];
+        self.pos += 1;
+        Ok(unescape_text(raw))
+    }
+
+    /// Stores a property in `node`. Fails only for values the rest of the file depends on.
+    fn apply_property(&mut self, node: &mut SgfNode, key: &str, values: &[String]) -> Result<(), SyntaxError> {
         match key {
             "SZ" => {
                 if let Some(first_value) = values.first() {
-                    board_size = first_value.parse::<usize>().ok();
+                    let size = parse_board_size(first_value);
+                    self.board_size = Some(size.ok_or_else(|| SyntaxError::InvalidBoardSize(first_value.clone()))?);
                 }
             }
+            // The text has already been decoded, see `decode_sgf`; saving sets CA[UTF-8]
+            "CA" => {}
             "AB" => {
-                for value in values {
-                    if value.len() == 2 {
-                        ab.push(sgf_coords_to_xy(value));
-                    }
-                }
+                let points = take_points(node, key, values);
+                node.setup.black.extend(points);
             }
             "AW" => {
-                for value in values {
-                    if value.len() == 2 {
-                        aw.push(sgf_coords_to_xy(value));
-                    }
-                }
+                let points = take_points(node, key, values);
+                node.setup.white.extend(points);
+            }
+            "AE" => {
+                let points = take_points(node, key, values);
+                node.setup.empty.extend(points);
             }
             "B" | "W" => {
-                if let Some(first_value) = values.first() {
-                    if first_value.len() == 2 {
-                        let (x, y) = sgf_coords_to_xy(first_value);
-                        let player = if key == "B" { Player::Black } else { Player::White };
-                        moves.push(Move { player, x, y, comment: None, triangles: Vec::new() });
+                let player = if key == "B" { Player::Black } else { Player::White };
+                match values.first().map(String::as_str) {
+                    // An empty value is a pass, and so is "tt" on boards up to 19x19
+                    None | Some("") => node.mv = Some(Move { player, point: None }),
+                    Some("tt") if self.board_size.is_none_or(|(w, h)| w <= 19 && h <= 19) => {
+                        node.mv = Some(Move { player, point: None })
                     }
+                    Some(value) => match sgf_coords_to_xy(value) {
+                        Some(point) => node.mv = Some(Move { player, point: Some(point) }),
+                        None => node.push_property(key, values),
+                    },
                 }
             }
-            "C" => {
-                if let Some(first_value) = values.first() {
-                    if let Some(last) = moves.last_mut() {
-                        last.comment = Some(first_value.to_string());
+            "C" => match (&node.comment, values.split_first()) {
+                (None, Some((first, rest))) => {
+                    node.comment = Some(first.to_string());
+                    if !rest.is_empty() {
+                        node.push_property(key, rest);
                     }
                 }
-            }
+                // A second comment is kept as it is rather than replacing the first
+                _ => node.push_property(key, values),
+            },
             "TR" => {
+                let points = take_points(node, key, values);
+                node.markup.triangles.extend(points);
+            }
+            "CR" => {
+                let points = take_points(node, key, values);
+                node.markup.circles.extend(points);
+            }
+            "SQ" => {
+                let points = take_points(node, key, values);
+                node.markup.squares.extend(points);
+            }
+            "MA" => {
+                let points = take_points(node, key, values);
+                node.markup.crosses.extend(points);
+            }
+            "SL" => {
+                let points = take_points(node, key, values);
+                node.markup.selected.extend(points);
+            }
+            "DD" => {
+                let points = take_points(node, key, values);
+                // Only values that could all be read make a DD of its own; otherwise the
+                // raw property kept by `take_points` would be written out next to it
+                if !points.is_empty() || node.property(key).is_none() {
+                    node.markup.dimmed.get_or_insert_with(Vec::new).extend(points);
+                }
+            }
+            "LB" => {
+                let mut rejected = Vec::new();
                 for value in values {
-                    if value.len() == 2 {
-                        if let Some(last) = moves.last_mut() {
-                            last.triangles.push(sgf_coords_to_xy(value));
-                        }
+                    match value.split_once(':').and_then(|(point, text)| Some((sgf_coords_to_xy(point)?, text))) {
+                        Some((point, text)) => node.markup.labels.push((point, text.to_string())),
+                        None => rejected.push(value.clone()),
                     }
                 }
+                if !rejected.is_empty() {
+                    node.push_property(key, &rejected);
+                }
             }
-            _ => {
-                if let Some(first_value) = values.first() {
-                    if first_value.len() > 0 {
-                        metadata.push((key.to_string(), first_value.to_string()));
-                    }
+            "BM" | "TE" | "DO" | "IT" => {
+                let annotation = match key {
+                    "BM" => MoveAnnotation::Bad,
+                    "TE" => MoveAnnotation::Tesuji,
+                    "DO" => MoveAnnotation::Doubtful,
+                    _ => MoveAnnotation::Interesting,
+                };
+                match parse_double(values) {
+                    Some(emphasized) => node.annotations.move_annotation = Some((annotation, emphasized)),
+                    None => node.push_property(key, values),
+                }
+            }
+            "GB" | "GW" | "DM" | "UC" => {
+                let annotation = match key {
+                    "GB" => PositionAnnotation::GoodForBlack,
+                    "GW" => PositionAnnotation::GoodForWhite,
+                    "DM" => PositionAnnotation::Even,
+                    _ => PositionAnnotation::Unclear,
+                };
+                match parse_double(values) {
+                    Some(emphasized) => node.annotations.position = Some((annotation, emphasized)),
+                    None => node.push_property(key, values),
                 }
             }
+            "HO" => match parse_double(values) {
+                Some(emphasized) => node.annotations.hotspot = Some(emphasized),
+                None => node.push_property(key, values),
+            },
+            "V" => {
+                match values.first().and_then(|v| v.trim().parse().ok()) {
+                    Some(value) => node.annotations.value = Some(value),
+                    None => node.push_property(key, values),
+                }
+            }
+            "XL" => node.move_labels.extend(values.iter().filter(|v| !v.is_empty()).cloned()),
+            "AR" | "LN" => {
+                let (pairs, rejected): (Vec<_>, Vec<_>) = values.iter().partition(|v| parse_point_pair(v).is_some());
+                let pairs = pairs.into_iter().filter_map(|v| parse_point_pair(v));
+                if key == "AR" {
+                    node.markup.arrows.extend(pairs);
+                } else {
+                    node.markup.lines.extend(pairs);
+                }
+                if !rejected.is_empty() {
+                    node.push_property(key, &rejected.into_iter().cloned().collect::<Vec<_>>());
+                }
+            }
+            _ => node.push_property(key, values),
         }
+        Ok(())
     }
+}
+
+/// Turns the raw bytes of an SGF file into text. The encoding is `forced` if given,
+/// otherwise the one named by the file's CA property. Without either, UTF-8 is used
+/// when the bytes are valid UTF-8 and Latin-1, the SGF default, when they are not.
+pub fn decode_sgf(bytes: &[u8], forced: Option<&'static Encoding>) -> String {
+    let declared = forced.or_else(|| declared_charset(bytes).and_then(|label| Encoding::for_label(label.trim().as_bytes())));
+    let encoding = match declared {
+        Some(encoding) => encoding,
+        None if std::str::from_utf8(bytes).is_ok() => UTF_8,
+        None => WINDOWS_1252,
+    };
+    // Characters that don't exist in the encoding become U+FFFD rather than failing the load
+    encoding.decode(bytes).0.into_owned()
+}
 
-    let board_size = board_size.ok_or(SgfParseError::MissingBoardSize)?;
-    Ok(SgfData { board_size, moves, ab, aw, metadata })
+/// Value of the first CA property in the raw file. Property names and brackets are
+/// ASCII, which every charset SGF files use leaves unchanged.
+fn declared_charset(bytes: &[u8]) -> Option<&str> {
+    // A property starts a node or follows another property's value, so "CA[" inside
+    // a value, e.g. in a comment, doesn't count
+    let starts_property = |i: usize| {
+        let before = &bytes[..i];
+        match before.iter().rposition(|b| !b.is_ascii_whitespace()) {
+            Some(j) if before[j] == b';' => true,
+            Some(j) if before[j] == b']' => {
+                // Not an escaped "\]", which needs an odd number of backslashes in front
+                before[..j].iter().rev().take_while(|&&b| b == b'\\').count() % 2 == 0
+            }
+            _ => false,
+        }
+    };
+    let start = (0..bytes.len()).find(|&i| {
+        bytes[i..].starts_with(b"CA") && starts_property(i)
+            && bytes[i + 2..].iter().find(|b| !b.is_ascii_whitespace()) == Some(&b'[')
+    })?;
+    let value = &bytes[start..];
+    let open = value.iter().position(|&b| b == b'[')? + 1;
+    let len = value[open..].iter().position(|&b| b == b']')?;
+    std::str::from_utf8(&value[open..open + len]).ok()
 }
 
-fn sgf_coords_to_xy(s: &str) -> (usize, usize) {
-    // SGF coords: 'aa' = (0,0), 'ab' = (0,1), etc.
-    let bytes = s.as_bytes();
-    (
-        (bytes[0] - b'a') as usize,
-        (bytes[1] - b'a') as usize,
-    )
+/// Writes a whole collection, one game tree per line
+pub fn collection_to_string(games: &[SgfData]) -> Result<String, ()> {
+    let mut out = String::new();
+    for game in games {
+        out.push_str(&sgf_to_string(game)?);
+        out.push('\n');
+    }
+    Ok(out)
+}
+
+/// Parses every game tree of a collection. There is always at least one game.
+pub fn parse_sgf(sgf: &str) -> Result<Vec<SgfData>, SgfParseError> {
+    let mut parser = Parser {
+        src: sgf,
+        // Anything before the first game tree, such as a mail header, is ignored
+        pos: sgf.find('(').unwrap_or(0),
+        board_size: None,
+    };
+    let mut games = Vec::new();
+    loop {
+        parser.board_size = None;
+        let root = parser.game_tree()?;
+        // Without SZ the board is 19x19
+        let (board_width, board_height) = parser.board_size.unwrap_or((19, 19));
+        games.push(SgfData { board_width, board_height, root });
+        // Whatever follows the last game tree is ignored, like a header before the first
+        parser.skip_whitespace();
+        if parser.peek() != Some(b'(') {
+            break;
+        }
+    }
+    Ok(games)
+}
+
+/// Older versions of this program stored labels in the comment itself, as
+/// "comment_label1_label2". This moves them out into XL, unless the collection
+/// already has XL labels. Comments from other programs may well contain '_',
+/// so this only runs when asked for. Returns how many comments were split.
+pub fn migrate_comment_labels(games: &mut [SgfData]) -> usize {
+    fn has_move_labels(node: &SgfNode) -> bool {
+        !node.move_labels.is_empty() || node.children.iter().any(has_move_labels)
+    }
+    fn migrate(node: &mut SgfNode) -> usize {
+        let mut migrated = 0;
+        if let Some(comment) = node.comment.take() {
+            match comment.split_once('_') {
+                Some((text, labels)) => {
+                    node.move_labels = labels
+                        .split('_')
+                        .map(str::trim)
+                        .filter(|s| !s.is_empty())
+                        .map(String::from)
+                        .collect();
+                    node.comment = (!text.trim().is_empty()).then(|| text.to_string());
+                    migrated += 1;
+                }
+                None => node.comment = Some(comment),
+            }
+        }
+        migrated + node.children.iter_mut().map(migrate).sum::<usize>()
+    }
+    if games.iter().any(|game| has_move_labels(&game.root)) {
+        return 0;
+    }
+    games.iter_mut().map(|game| migrate(&mut game.root)).sum()
+}
+
+/// Reads an SGF Double: "1" is normal and "2" emphasized. `None` for anything else.
+fn parse_double(values: &[String]) -> Option<bool> {
+    match values.first().map(|v| v.trim()) {
+        Some("1") => Some(false),
+        Some("2") => Some(true),
+        _ => None,
+    }
+}
+
+/// Parses SZ, either "19" for a square board or "19:13" for width and height
+fn parse_board_size(value: &str) -> Option<(usize, usize)> {
+    let (width, height) = match value.split_once(':') {
+        Some((w, h)) => (w.trim().parse().ok()?, h.trim().parse().ok()?),
+        None => {
+            let size = value.trim().parse().ok()?;
+            (size, size)
+        }
+    };
+    let valid = 1..=MAX_BOARD_SIZE;
+    (valid.contains(&width) && valid.contains(&height)).then_some((width, height))
+}
+
+/// Reads a "from:to" pair of points, as used by AR and LN
+fn parse_point_pair(value: &str) -> Option<PointPair> {
+    let (from, to) = value.split_once(':')?;
+    Some((sgf_coords_to_xy(from)?, sgf_coords_to_xy(to)?))
+}
+
+/// Reads a list of points, expanding compressed rectangles such as "aa:cc".
+/// Values that aren't points are kept in `node` as a raw `key` property, so saving keeps them.
+fn take_points(node: &mut SgfNode, key: &str, values: &[String]) -> Vec<(usize, usize)> {
+    let mut points = Vec::new();
+    let mut rejected = Vec::new();
+    for value in values {
+        if let Some(((x1, y1), (x2, y2))) = parse_point_pair(value) {
+            for y in y1.min(y2)..=y1.max(y2) {
+                for x in x1.min(x2)..=x1.max(x2) {
+                    points.push((x, y));
+                }
+            }
+        } else if let Some(point) = sgf_coords_to_xy(value) {
+            points.push(point);
+        } else if !value.is_empty() {
+            // An empty value is an empty list, as in DD[]
+            rejected.push(value.clone());
+        }
+    }
+    if !rejected.is_empty() {
+        node.push_property(key, &rejected);
+    }
+    points
+}
+
+pub fn sgf_coords_to_xy(s: &str) -> Option<(usize, usize)> {
+    // SGF coords: 'aa' = (0,0), 'ab' = (0,1), ..., 'Az' = (26,25), etc.
+    let mut chars = s.chars();
+    match (chars.next(), chars.next(), chars.next()) {
+        (Some(a), Some(b), None) => Some((char_to_coord(a)?, char_to_coord(b)?)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(sgf: &str) -> String {
+        let games = parse_sgf(sgf).unwrap();
+        collection_to_string(&games).unwrap()
+    }
+
+    #[test]
+    fn keeps_underscores_in_comments() {
+        let sgf = "(;SZ[19]CA[UTF-8];B[pd]C[kgs_user [3k\\]: nice move];W[dp]C[see http://a.com/x_y])\n";
+        let games = parse_sgf(sgf).unwrap();
+        let first = &games[0].root.children[0];
+        assert_eq!(first.comment.as_deref(), Some("kgs_user [3k]: nice move"));
+        assert!(first.move_labels.is_empty());
+        assert_eq!(round_trip(sgf), sgf);
+    }
+
+    #[test]
+    fn migrates_comment_labels_on_request() {
+        let mut games = parse_sgf("(;SZ[19];B[pd]C[good_A_B])").unwrap();
+        assert_eq!(migrate_comment_labels(&mut games), 1);
+        let node = &games[0].root.children[0];
+        assert_eq!(node.comment.as_deref(), Some("good"));
+        assert_eq!(node.move_labels, ["A", "B"]);
+
+        let mut games = parse_sgf("(;SZ[19];B[pd]XL[A];W[dp]C[a_b])").unwrap();
+        assert_eq!(migrate_comment_labels(&mut games), 0);
+    }
+
+    #[test]
+    fn rejects_invalid_board_size() {
+        for (sgf, value) in [("(;GM[1]SZ[60])", "60"), ("(;SZ[abc];B[aa])", "abc"), ("(;SZ[19:0])", "19:0")] {
+            match parse_sgf(sgf) {
+                Err(SgfParseError::Syntax { kind: SyntaxError::InvalidBoardSize(v), offset, .. }) => {
+                    assert_eq!(v, value);
+                    assert_eq!(offset, sgf.find("SZ").unwrap());
+                }
+                other => panic!("{} parsed as {:?}", sgf, other.map(|games| games.len())),
+            }
+        }
+    }
+
+    #[test]
+    fn round_trips_variations_and_escapes() {
+        let sgf = "(;SZ[19]CA[UTF-8];B[pd](;W[dp];B[])(;W[dd]C[a \\] b \\\\ c]))\n";
+        assert_eq!(round_trip(sgf), sgf);
+        let games = parse_sgf(sgf).unwrap();
+        let first = &games[0].root.children[0];
+        assert_eq!(first.children.len(), 2);
+        assert_eq!(first.children[1].comment.as_deref(), Some("a ] b \\ c"));
+    }
+
+    #[test]
+    fn round_trips_markup_and_annotations() {
+        let sgf = "(;SZ[9]CA[UTF-8];B[cc]TR[aa][bb]CR[cc]SQ[dd]MA[ee]SL[ff]DD[]LB[gg:A]AR[aa:cc]LN[bb:dd]BM[2]GB[1]HO[1]V[0.5]XL[ko])\n";
+        assert_eq!(round_trip(sgf), sgf);
+    }
+
+    #[test]
+    fn reads_passes() {
+        // "tt" is a pass on boards up to 19x19 and an ordinary point on larger ones
+        assert_eq!(round_trip("(;SZ[9];B[tt];W[])"), "(;SZ[9]CA[UTF-8];B[];W[])\n");
+        assert_eq!(round_trip("(;SZ[25];B[tt])"), "(;SZ[25]CA[UTF-8];B[tt])\n");
+    }
+
+    #[test]
+    fn round_trips_rectangular_boards_and_collections() {
+        let sgf = "(;SZ[19:13]CA[UTF-8];B[sm])\n(;SZ[13]CA[UTF-8];W[aa])\n";
+        let games = parse_sgf(sgf).unwrap();
+        assert_eq!((games[0].board_width, games[0].board_height), (19, 13));
+        assert_eq!(games[1].board_width, 13);
+        assert_eq!(round_trip(sgf), sgf);
+        // Without SZ the board is 19x19
+        assert_eq!(parse_sgf("(;B[aa])").unwrap()[0].board_width, 19);
+    }
+
+    #[test]
+    fn reports_error_positions() {
+        let error = |sgf: &str| match parse_sgf(sgf) {
+            Err(SgfParseError::Syntax { kind, offset, line, column, .. }) => (kind.to_string(), offset, line, column),
+            Ok(_) => panic!("{} parsed", sgf),
+        };
+        assert_eq!(error("(;SZ[9]\n;B[aa]C[x)"), (SyntaxError::UnterminatedValue.to_string(), 15, 2, 8));
+        assert_eq!(error("(;SZ[9];B[aa]"), (SyntaxError::UnclosedGameTree.to_string(), 13, 1, 14));
+        assert_eq!(error("(;SZ[9]; [aa])"), (SyntaxError::UnexpectedChar('[').to_string(), 9, 1, 10));
+        assert_eq!(error("SZ[9]"), (SyntaxError::ExpectedGameTree.to_string(), 0, 1, 1));
+    }
+
+    #[test]
+    fn keeps_values_it_cannot_read() {
+        for sgf in [
+            "(;SZ[19]CA[UTF-8];B[a])\n",
+            "(;SZ[19]CA[UTF-8];B[aa]LB[aa])\n",
+            "(;SZ[19]CA[UTF-8]TR[aa]TR[z!]CR[x]AR[aa]LN[x:y])\n",
+            "(;SZ[19]CA[UTF-8]C[one]C[two])\n",
+            "(;SZ[19]CA[UTF-8]BM[3]HO[]DD[q])\n",
+        ] {
+            assert_eq!(round_trip(sgf), sgf);
+        }
+    }
+
+    #[test]
+    fn validates_game_info() {
+        let cases = [
+            ("RE", "B+R", true),
+            ("RE", "W+3.5", true),
+            ("RE", "B+", true),
+            ("RE", "W+Time", true),
+            ("RE", "Draw", true),
+            ("RE", "0", true),
+            ("RE", "?", true),
+            ("RE", "B+0", false),
+            ("RE", "B+-2", false),
+            ("RE", "Black wins", false),
+            ("DT", "2024-05-01", true),
+            ("DT", "2024-05", true),
+            ("DT", "2024", true),
+            ("DT", "2024-05-01,02", true),
+            ("DT", "2024-05-31,06-01", true),
+            ("DT", "2024-05-01, 2025-01-03", true),
+            ("DT", "05-01", false),
+            ("DT", "01", false),
+            ("DT", "2024-13-01", false),
+            ("DT", "2024-05-32", false),
+            ("DT", "2024-5-1", false),
+            ("DT", "May 2024", false),
+            ("KM", "6.5", true),
+            ("KM", "-7", true),
+            ("KM", "six", false),
+            ("KM", "inf", false),
+            ("HA", "2", true),
+            ("HA", "-1", false),
+            ("TM", "300", true),
+            ("TM", "5 min", false),
+            ("PB", "anything at all", true),
+        ];
+        for (key, value, valid) in cases {
+            assert_eq!(validate_game_info(key, value).is_ok(), valid, "{}[{}]", key, value);
+        }
+    }
+
+    #[test]
+    fn decodes_by_declared_charset() {
+        for (charset, encoding) in [("GBK", encoding_rs::GBK), ("Shift_JIS", encoding_rs::SHIFT_JIS)] {
+            let comment = "黒棋の手";
+            let mut bytes = format!("(;CA[{}]PB[", charset).into_bytes();
+            bytes.extend_from_slice(&encoding.encode("本因坊").0);
+            bytes.extend_from_slice(b"];B[pd]C[");
+            bytes.extend_from_slice(&encoding.encode(comment).0);
+            bytes.extend_from_slice(b"])");
+            let games = parse_sgf(&decode_sgf(&bytes, None)).unwrap();
+            assert_eq!(games[0].root.property("PB"), Some("本因坊"));
+            assert_eq!(games[0].root.children[0].comment.as_deref(), Some(comment));
+            // Saved as UTF-8, and read back the same without any help
+            let saved = collection_to_string(&games).unwrap();
+            assert_eq!(decode_sgf(saved.as_bytes(), None), saved);
+        }
+    }
+
+    #[test]
+    fn finds_charset_only_where_a_property_can_start() {
+        assert_eq!(declared_charset(b"(;CA[GBK])"), Some("GBK"));
+        assert_eq!(declared_charset(b"(;FF[4] CA[GBK])"), Some("GBK"));
+        assert_eq!(declared_charset(b"(;GN[see CA[Big5\\]]CA[GBK])"), Some("GBK"));
+        assert_eq!(declared_charset(b"(;C[x\\]CA[Big5]])"), None);
+        assert_eq!(declared_charset(b"(;C[xCA[Big5]])"), None);
+    }
 }
@@ -0,0 +1,167 @@
+use unicode_width::UnicodeWidthChar;
+
+/// Multi-line text being edited, with a cursor kept as a char offset into the text
+pub struct TextArea {
+    pub text: String,
+    cursor: usize,
+}
+
+impl TextArea {
+    /// Starts editing `text` with the cursor at its end
+    pub fn new(text: String) -> Self {
+        let cursor = text.chars().count();
+        TextArea { text, cursor }
+    }
+
+    fn byte_offset(&self, chars: usize) -> usize {
+        self.text.char_indices().nth(chars).map_or(self.text.len(), |(i, _)| i)
+    }
+
+    fn char_count(&self) -> usize {
+        self.text.chars().count()
+    }
+
+    /// Char offset where the line holding char offset `at` starts
+    fn line_start(&self, at: usize) -> usize {
+        let chars: Vec<char> = self.text.chars().collect();
+        (0..at).rev().find(|&i| chars[i] == '\n').map_or(0, |i| i + 1)
+    }
+
+    /// Char offset of the newline ending the line holding char offset `at`, or the end of the text
+    fn line_end(&self, at: usize) -> usize {
+        self.text.chars().skip(at).position(|c| c == '\n').map_or(self.char_count(), |i| at + i)
+    }
+
+    pub fn insert(&mut self, c: char) {
+        let at = self.byte_offset(self.cursor);
+        self.text.insert(at, c);
+        self.cursor += 1;
+    }
+
+    pub fn backspace(&mut self) {
+        if self.cursor > 0 {
+            self.cursor -= 1;
+            let at = self.byte_offset(self.cursor);
+            self.text.remove(at);
+        }
+    }
+
+    pub fn delete(&mut self) {
+        if self.cursor < self.char_count() {
+            let at = self.byte_offset(self.cursor);
+            self.text.remove(at);
+        }
+    }
+
+    pub fn left(&mut self) {
+        self.cursor = self.cursor.saturating_sub(1);
+    }
+
+    pub fn right(&mut self) {
+        self.cursor = (self.cursor + 1).min(self.char_count());
+    }
+
+    pub fn home(&mut self) {
+        self.cursor = self.line_start(self.cursor);
+    }
+
+    pub fn end(&mut self) {
+        self.cursor = self.line_end(self.cursor);
+    }
+
+    /// Moves to the same column of the previous line, or its end if it is shorter
+    pub fn up(&mut self) {
+        let start = self.line_start(self.cursor);
+        if start == 0 {
+            self.cursor = 0;
+            return;
+        }
+        let column = self.cursor - start;
+        let prev_start = self.line_start(start - 1);
+        self.cursor = (prev_start + column).min(start - 1);
+    }
+
+    /// Moves to the same column of the next line, or its end if it is shorter
+    pub fn down(&mut self) {
+        let end = self.line_end(self.cursor);
+        if end == self.char_count() {
+            self.cursor = end;
+            return;
+        }
+        let column = self.cursor - self.line_start(self.cursor);
+        self.cursor = (end + 1 + column).min(self.line_end(end + 1));
+    }
+
+    /// Rows of the text wrapped to `width` cells, and the row and cell column the cursor is on
+    pub fn wrapped(&self, width: usize) -> (Vec<String>, (usize, usize)) {
+        let rows = wrap_text(&self.text, width);
+        let row = rows.iter().rposition(|(start, _)| *start <= self.cursor).unwrap_or(0);
+        let column = rows.get(row).map_or(0, |(start, text)| {
+            text.chars().take(self.cursor - start).map(char_width).sum()
+        });
+        (rows.into_iter().map(|(_, text)| text).collect(), (row, column))
+    }
+}
+
+/// Terminal cells a char takes up: two for CJK and other wide characters
+fn char_width(c: char) -> usize {
+    c.width().unwrap_or(0)
+}
+
+/// Breaks `text` into rows of at most `width` terminal cells, at spaces where possible.
+/// Each row comes with the char offset in `text` where it starts.
+pub fn wrap_text(text: &str, width: usize) -> Vec<(usize, String)> {
+    let width = width.max(1);
+    let mut rows = Vec::new();
+    let mut offset = 0;
+    for line in text.split('\n') {
+        let chars: Vec<char> = line.chars().collect();
+        let mut pos = 0;
+        loop {
+            // Take as many chars as fit, but always at least one so wide chars can't stall
+            let mut end = pos;
+            let mut cells = 0;
+            while end < chars.len() && (end == pos || cells + char_width(chars[end]) <= width) {
+                cells += char_width(chars[end]);
+                end += 1;
+            }
+            if end == chars.len() {
+                rows.push((offset + pos, chars[pos..].iter().collect()));
+                break;
+            }
+            // Break after the last space that still fits, dropping the space itself
+            match (pos + 1..=end).rev().find(|&i| chars[i] == ' ') {
+                Some(space) => {
+                    rows.push((offset + pos, chars[pos..space].iter().collect()));
+                    pos = space + 1;
+                }
+                None => {
+                    rows.push((offset + pos, chars[pos..end].iter().collect()));
+                    pos = end;
+                }
+            }
+        }
+        offset += chars.len() + 1;
+    }
+    rows
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wraps_wide_chars_by_cell_width() {
+        let rows: Vec<String> = wrap_text("黑棋先手很好", 6).into_iter().map(|(_, text)| text).collect();
+        assert_eq!(rows, ["黑棋先", "手很好"]);
+        let rows: Vec<String> = wrap_text("白棋 好手", 5).into_iter().map(|(_, text)| text).collect();
+        assert_eq!(rows, ["白棋", "好手"]);
+    }
+
+    #[test]
+    fn cursor_column_counts_cells() {
+        let mut input = TextArea::new(String::from("黑棋先手很好"));
+        input.left();
+        assert_eq!(input.wrapped(6).1, (1, 4));
+    }
+}
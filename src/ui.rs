@@ -1,42 +1,85 @@
-use crate::game::{GoGame, Stone};
-use crate::sgf_parser::sgf_to_string;
-use crate::ui_mode_actions::handle_edit_triangles_input;
+use crate::game::{create_new_file, GoGame, Status, Stone};
+use crate::text_area::{wrap_text, TextArea};
+use crate::sgf_parser::{coord_to_char, MarkupKind, GAME_INFO_PROPERTIES, MoveAnnotation, Player, SetupKind, SgfNode};
+use crate::ui_mode_actions::format_point_input;
+use crate::ui_mode_actions::{handle_edit_markup_input, MARKUP_MENU_KEYS};
 use crossterm::event::{self, Event, KeyCode, KeyEventKind};
 use ratatui::prelude::*;
 use ratatui::widgets::{Block, Borders, Paragraph};
-use std::io::{self, Stdout};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::io::{self, Stdout, Write};
 
 pub enum UiMode {
     Normal,
     GotoMoveInput { input: String },
     HotkeyHelp,
-    ModifyMoveInput { input: String },
+    ModifyMoveInput { input: String, error: Option<String> },
     SearchCoordInput { input: String },
-    EditCommentInput { input: String },
+    EditCommentInput { input: TextArea },
     EditLabelInput { input: String },
-    EditTrianglesInput { input: String },
-    InsertMoveInput { input: String, color: crate::sgf_parser::Player },
+    GamePicker { selected: usize },
+    /// One value per entry of `GAME_INFO_PROPERTIES`
+    GameInfoEditor { values: Vec<String>, selected: usize, error: Option<String> },
+    MarkupMenu,
+    EditMarkupInput { kind: MarkupKind, input: String },
+    /// One input per kind, in `SetupKind::ALL` order
+    EditSetupInput { kind: SetupKind, inputs: [String; 3] },
+    InsertMoveInput { input: String, color: crate::sgf_parser::Player, error: Option<String> },
+    /// Command typed after ':', shown in the status line
+    CommandInput { input: String },
+    ConfirmQuit,
+    /// Editing straight on the board at the (x, y) cursor
+    BoardEdit { cursor: (usize, usize), color: Player },
 }
 
 
 pub fn run_ui(game: &mut GoGame) -> io::Result<()> {
     let mut terminal = setup_terminal()?;
-    let mut mode = UiMode::Normal;
+    // Collections start out in the game picker
+    let mut mode = if game.games.len() > 1 {
+        UiMode::GamePicker { selected: game.game_idx }
+    } else {
+        UiMode::Normal
+    };
+    // Rows the comment pane is scrolled down by; any key but PageUp/PageDown resets it
+    let mut comment_scroll = 0;
     loop {
         terminal.draw(|f| {
             let size = f.size();
-            let board = render_board(game);
+            let cursor = match &mode {
+                UiMode::BoardEdit { cursor, .. } => Some(*cursor),
+                _ => None,
+            };
+            let board = render_board(game, cursor);
             let meta = render_metadata(game);
             let chunks = Layout::default()
                 .direction(Direction::Vertical)
                 .margin(2)
                 .constraints([
-                    Constraint::Length((game.board_size + 3) as u16),
+                    Constraint::Length(1),
+                    Constraint::Length((game.board_height + 3) as u16),
                     Constraint::Min(3),
+                    Constraint::Length(1),
                 ])
                 .split(size);
-            f.render_widget(board, chunks[0]);
-            f.render_widget(meta, chunks[1]);
+            let top = Layout::default()
+                .direction(Direction::Horizontal)
+                .constraints([
+                    Constraint::Length((game.board_width * 2 + 3) as u16),
+                    Constraint::Min(10),
+                ])
+                .split(chunks[1]);
+            f.render_widget(render_title(game), chunks[0]);
+            f.render_widget(board, top[0]);
+            f.render_widget(render_game_tree(game, top[1]), top[1]);
+            let bottom = Layout::default()
+                .direction(Direction::Horizontal)
+                .constraints([Constraint::Percentage(50), Constraint::Percentage(50)])
+                .split(chunks[2]);
+            f.render_widget(meta, bottom[0]);
+            f.render_widget(render_comment(game, bottom[1], &mut comment_scroll), bottom[1]);
+            f.render_widget(render_status_line(game, &mode), chunks[3]);
 
             // Use a reference to the current mode so popup input is live
             match &mode {
@@ -47,39 +90,71 @@ pub fn run_ui(game: &mut GoGame) -> io::Result<()> {
                     f.render_widget(text, area);
                 }
                 UiMode::HotkeyHelp => {
-                    let area = centered_rect(50, 40, size);
+                    let area = centered_rect(60, 90, size);
                     let block = Block::default().title("Hotkey Help").borders(Borders::ALL).border_style(Style::default().fg(Color::Yellow));
                     let help = [
                         "q         Quit",
                         "n / →     Next move",
                         "p / ←     Previous move",
+                        "↓ / ↑     Next/previous variation",
+                        "b         Back to branch point",
                         "]         Next commented move",
                         "[         Previous commented move",
+                        "}         Next bad move",
+                        "{         Previous bad move",
                         "g         Goto move number",
+                        "G         Pick another game of the collection",
+                        "I         Edit game info (players, result, date...)",
                         "m         Modify current move",
                         "/         Search for coordinate",
-                        "c         Add/Edit move comment",
+                        "c         Add/Edit move comment (Ctrl-s saves it)",
+                        "C         Edit move comment in $EDITOR",
+                        "PgUp/PgDn Scroll the comment pane",
                         "l         Add/Edit move labels",
                         "t         Add/Edit triangles",
+                        "a         Add/Edit other markup",
+                        "e         Add/Remove setup stones",
+                        "v         Board edit mode: move a cursor with arrows/hjkl,",
+                        "          Enter/Space plays there as a new variation (Tab switches",
+                        "          colour), t/o/s/x/d/e toggle markup,",
+                        "          L a label, B/W/E setup stones; Esc leaves",
+                        "? / !     Cycle move annotation (? ?? ?! / ! !! !?)",
+                        "=         Cycle position annotation",
+                        "*         Toggle hotspot",
                         "i         Insert new move",
                         "x         Remove current move",
+                        "u         Undo last edit",
+                        "Ctrl-r    Redo undone edit",
+                        ":w        Save (:w <path> or :saveas <path> to save elsewhere)",
+                        ":q        Quit (:q! discards unsaved edits, :wq saves first)",
                         "h         Show this help",
                         "Esc/Enter Close this help",
                     ].join("\n");
                     let text = Paragraph::new(help).style(Style::default().fg(Color::Yellow)).block(block);
                     f.render_widget(text, area);
                 }
-                UiMode::ModifyMoveInput { input } => {
+                UiMode::ModifyMoveInput { input, error } => {
                     let area = centered_rect(30, 10, size);
                     let block = Block::default().title("Modify Move").borders(Borders::ALL).border_style(Style::default().fg(Color::Yellow));
-                    let text = Paragraph::new(format!("Enter coords (e.g., dd): {}", input)).block(block);
+                    let mut lines = vec![Line::raw(format!("Enter coords (e.g., dd): {}", input))];
+                    if let Some(error) = error {
+                        lines.push(Line::styled(error.as_str(), Style::default().fg(Color::Red)));
+                    }
+                    let text = Paragraph::new(lines).block(block);
                     f.render_widget(text, area);
                 }
                 UiMode::EditCommentInput { input } => {
-                    let area = centered_rect(50, 10, size);
-                    let block = Block::default().title("Edit Comment").borders(Borders::ALL).border_style(Style::default().fg(Color::Yellow));
-                    let text = Paragraph::new(format!("Edit comment (no underscores): {}", input)).style(Style::default().fg(Color::Yellow)).block(block);
-                    f.render_widget(text, area);
+                    let area = centered_rect(70, 60, size);
+                    let block = Block::default().title("Edit Comment (Ctrl-s to save, Esc to cancel)").borders(Borders::ALL).border_style(Style::default().fg(Color::Yellow));
+                    let inner = block.inner(area);
+                    // One cell is kept free for the cursor at the end of a full row
+                    let (rows, (row, column)) = input.wrapped(inner.width.saturating_sub(1) as usize);
+                    let visible = (inner.height as usize).max(1);
+                    let first = row.saturating_sub(visible - 1);
+                    let lines: Vec<Line> = rows.into_iter().skip(first).take(visible).map(Line::raw).collect();
+                    f.render_widget(ratatui::widgets::Clear, area);
+                    f.render_widget(Paragraph::new(lines).style(Style::default().fg(Color::Yellow)).block(block), area);
+                    f.set_cursor(inner.x + column as u16, inner.y + (row - first) as u16);
                 }
                 UiMode::EditLabelInput { input } => {
                     let area = centered_rect(60, 10, size);
@@ -87,10 +162,91 @@ pub fn run_ui(game: &mut GoGame) -> io::Result<()> {
                     let text = Paragraph::new(format!("Comma-separated labels (e.g., attack,urgent,ko): {}", input)).style(Style::default().fg(Color::Yellow)).block(block);
                     f.render_widget(text, area);
                 }
-                UiMode::EditTrianglesInput { input } => {
+                UiMode::ConfirmQuit => {
+                    let area = centered_rect(50, 20, size);
+                    let block = Block::default().title("Unsaved Changes").borders(Borders::ALL).border_style(Style::default().fg(Color::Yellow));
+                    let text = Paragraph::new("Save before quitting?\n\ny  Save and quit\nn  Quit without saving\nEsc  Keep editing")
+                        .style(Style::default().fg(Color::Yellow))
+                        .block(block);
+                    f.render_widget(text, area);
+                }
+                UiMode::GamePicker { selected } => {
+                    let area = centered_rect(80, 60, size);
+                    let block = Block::default().title("Select Game (Enter to open)").borders(Borders::ALL).border_style(Style::default().fg(Color::Yellow));
+                    // Scroll so the selected game stays in view
+                    let rows = area.height.saturating_sub(2).max(1) as usize;
+                    let first = selected.saturating_sub(rows - 1);
+                    let lines: Vec<Line> = game.games
+                        .iter()
+                        .enumerate()
+                        .skip(first)
+                        .take(rows)
+                        .map(|(i, sgf)| {
+                            let text = format!("{:>3}. {}", i + 1, game_summary(&sgf.root));
+                            if i == *selected {
+                                Line::styled(text, Style::default().fg(Color::Black).bg(Color::Yellow))
+                            } else {
+                                Line::styled(text, Style::default().fg(Color::Yellow))
+                            }
+                        })
+                        .collect();
+                    f.render_widget(Paragraph::new(lines).block(block), area);
+                }
+                UiMode::GameInfoEditor { values, selected, error } => {
+                    let area = centered_rect(60, 70, size);
+                    let block = Block::default().title("Game Info").borders(Borders::ALL).border_style(Style::default().fg(Color::Yellow));
+                    let mut lines: Vec<Line> = GAME_INFO_PROPERTIES
+                        .iter()
+                        .zip(values)
+                        .enumerate()
+                        .map(|(i, ((key, name), value))| {
+                            let text = format!("{:<13} {}  {}", name, key, value);
+                            if i == *selected {
+                                Line::styled(text, Style::default().fg(Color::Black).bg(Color::Yellow))
+                            } else {
+                                Line::styled(text, Style::default().fg(Color::Yellow))
+                            }
+                        })
+                        .collect();
+                    lines.push(Line::raw(""));
+                    lines.push(Line::styled("↑/↓ choose field, type to edit, Enter to save, Esc to cancel", Style::default().fg(Color::Yellow)));
+                    if let Some(error) = error {
+                        lines.push(Line::styled(error.as_str(), Style::default().fg(Color::Red)));
+                    }
+                    f.render_widget(Paragraph::new(lines).block(block), area);
+                }
+                UiMode::MarkupMenu => {
+                    let area = centered_rect(30, 40, size);
+                    let block = Block::default().title("Edit Markup").borders(Borders::ALL).border_style(Style::default().fg(Color::Yellow));
+                    let mut menu: Vec<String> = MARKUP_MENU_KEYS
+                        .iter()
+                        .map(|(key, kind)| format!("{}    {}", key, kind.name()))
+                        .collect();
+                    menu.push("Esc  Cancel".to_string());
+                    let text = Paragraph::new(menu.join("\n")).style(Style::default().fg(Color::Yellow)).block(block);
+                    f.render_widget(text, area);
+                }
+                UiMode::EditMarkupInput { kind, input } => {
                     let area = centered_rect(60, 10, size);
-                    let block = Block::default().title("Edit Triangles").borders(Borders::ALL).border_style(Style::default().fg(Color::Yellow));
-                    let text = Paragraph::new(format!("Comma-separated coords (e.g., dd,ee,fg): {}", input)).style(Style::default().fg(Color::Yellow)).block(block);
+                    let block = Block::default().title(format!("Edit {}", kind.name())).borders(Borders::ALL).border_style(Style::default().fg(Color::Yellow));
+                    let prompt = match kind {
+                        MarkupKind::Label => "Comma-separated coord:text (e.g., dd:A,ee:B)",
+                        MarkupKind::Arrow | MarkupKind::Line => "Comma-separated coord-coord (e.g., dd-ff,ee-gg)",
+                        MarkupKind::Dim => "Comma-separated coords, or - to undim everything (e.g., dd,ee,fg)",
+                        _ => "Comma-separated coords (e.g., dd,ee,fg)",
+                    };
+                    let text = Paragraph::new(format!("{}: {}", prompt, input)).style(Style::default().fg(Color::Yellow)).block(block);
+                    f.render_widget(text, area);
+                }
+                UiMode::EditSetupInput { kind, inputs } => {
+                    let input = &inputs[*kind as usize];
+                    let area = centered_rect(60, 12, size);
+                    let block = Block::default().title("Edit Setup Stones").borders(Borders::ALL).border_style(Style::default().fg(Color::Yellow));
+                    let lines = vec![
+                        Line::raw(format!("Comma-separated coords (e.g., dd,ee,fg): {}", input)),
+                        Line::raw(format!("Editing: {} (Tab to switch, Enter saves all three, Esc cancels)", kind.name())),
+                    ];
+                    let text = Paragraph::new(lines).style(Style::default().fg(Color::Yellow)).block(block);
                     f.render_widget(text, area);
                 }
                 UiMode::SearchCoordInput { input } => {
@@ -99,14 +255,21 @@ pub fn run_ui(game: &mut GoGame) -> io::Result<()> {
                     let text = Paragraph::new(format!("Enter coords (e.g., dd): {}", input)).block(block);
                     f.render_widget(text, area);
                 }
-                UiMode::InsertMoveInput { input, color } => {
+                UiMode::InsertMoveInput { input, color, error } => {
                     let area = centered_rect(40, 12, size);
                     let block = Block::default().title("Insert Move").borders(Borders::ALL).border_style(Style::default().fg(Color::Yellow));
                     let color_str = match color {
                         crate::sgf_parser::Player::Black => "Black",
                         crate::sgf_parser::Player::White => "White",
                     };
-                    let text = Paragraph::new(format!("Enter coords (e.g., dd): {}\nColor: {} (Tab to toggle, Enter to confirm)", input, color_str)).style(Style::default().fg(Color::Yellow)).block(block);
+                    let mut lines = vec![
+                        Line::raw(format!("Enter coords (e.g., dd, empty to pass): {}", input)),
+                        Line::raw(format!("Color: {} (Tab to toggle, Enter to confirm)", color_str)),
+                    ];
+                    if let Some(error) = error {
+                        lines.push(Line::styled(error.as_str(), Style::default().fg(Color::Red)));
+                    }
+                    let text = Paragraph::new(lines).style(Style::default().fg(Color::Yellow)).block(block);
                     f.render_widget(text, area);
                 }
                 _ => {}
@@ -115,18 +278,42 @@ pub fn run_ui(game: &mut GoGame) -> io::Result<()> {
         if event::poll(std::time::Duration::from_millis(200))? {
             match event::read()? {
                 Event::Key(key) if key.kind == KeyEventKind::Press => {
+                    game.status = None;
                     let mode_ref = &mut mode;
                     match mode_ref {
-                        UiMode::InsertMoveInput { input, color } => {
-                            if let Some(new_mode) = crate::ui_mode_actions::handle_insert_move_input(&key, input, color, game) {
+                        UiMode::CommandInput { input } => {
+                            match crate::ui_mode_actions::handle_command_input(&key, input, game) {
+                                crate::ui_mode_actions::UiAction::Quit => break,
+                                crate::ui_mode_actions::UiAction::ChangeMode(new_mode) => *mode_ref = new_mode,
+                                crate::ui_mode_actions::UiAction::Continue | crate::ui_mode_actions::UiAction::EditCommentInEditor => {}
+                            }
+                        },
+                        UiMode::ConfirmQuit => {
+                            match crate::ui_mode_actions::handle_confirm_quit_input(&key, game) {
+                                crate::ui_mode_actions::UiAction::Quit => break,
+                                crate::ui_mode_actions::UiAction::ChangeMode(new_mode) => *mode_ref = new_mode,
+                                crate::ui_mode_actions::UiAction::Continue | crate::ui_mode_actions::UiAction::EditCommentInEditor => {}
+                            }
+                        },
+                        UiMode::InsertMoveInput { input, color, error } => {
+                            if let Some(new_mode) = crate::ui_mode_actions::handle_insert_move_input(&key, input, color, error, game) {
                                 *mode_ref = new_mode;
                             }
                         },
+                        UiMode::Normal if matches!(key.code, KeyCode::PageDown | KeyCode::PageUp) => {
+                            // Clamped to the comment's length when drawn
+                            comment_scroll = match key.code {
+                                KeyCode::PageDown => comment_scroll + 5,
+                                _ => comment_scroll.saturating_sub(5),
+                            };
+                        },
                         UiMode::Normal => {
+                            comment_scroll = 0;
                             match crate::ui_mode_actions::handle_normal_input(&key, game) {
                                 crate::ui_mode_actions::UiAction::Quit => break,
                                 crate::ui_mode_actions::UiAction::ChangeMode(new_mode) => *mode_ref = new_mode,
                                 crate::ui_mode_actions::UiAction::Continue => {}
+                                crate::ui_mode_actions::UiAction::EditCommentInEditor => edit_comment_in_editor(&mut terminal, game)?,
                             }
                         },
                         UiMode::GotoMoveInput { input } => {
@@ -143,8 +330,28 @@ pub fn run_ui(game: &mut GoGame) -> io::Result<()> {
                                 *mode_ref = new_mode;
                             }
                         },
-                        UiMode::EditTrianglesInput { input } => {
-                            if let Some(new_mode) = handle_edit_triangles_input(&key, input, game) {
+                        UiMode::GamePicker { selected } => {
+                            if let Some(new_mode) = crate::ui_mode_actions::handle_game_picker_input(&key, selected, game) {
+                                *mode_ref = new_mode;
+                            }
+                        },
+                        UiMode::GameInfoEditor { values, selected, error } => {
+                            if let Some(new_mode) = crate::ui_mode_actions::handle_game_info_input(&key, values, selected, error, game) {
+                                *mode_ref = new_mode;
+                            }
+                        },
+                        UiMode::MarkupMenu => {
+                            if let Some(new_mode) = crate::ui_mode_actions::handle_markup_menu_input(&key, game) {
+                                *mode_ref = new_mode;
+                            }
+                        },
+                        UiMode::EditMarkupInput { kind, input } => {
+                            if let Some(new_mode) = handle_edit_markup_input(&key, *kind, input, game) {
+                                *mode_ref = new_mode;
+                            }
+                        },
+                        UiMode::EditSetupInput { kind, inputs } => {
+                            if let Some(new_mode) = crate::ui_mode_actions::handle_edit_setup_input(&key, kind, inputs, game) {
                                 *mode_ref = new_mode;
                             }
                         },
@@ -158,12 +365,23 @@ pub fn run_ui(game: &mut GoGame) -> io::Result<()> {
                                 *mode_ref = new_mode;
                             }
                         },
-                        UiMode::ModifyMoveInput { input } => {
-                            if let Some(new_mode) = crate::ui_mode_actions::handle_modify_move_input(&key, input, game) {
+                        UiMode::ModifyMoveInput { input, error } => {
+                            if let Some(new_mode) = crate::ui_mode_actions::handle_modify_move_input(&key, input, error, game) {
+                                *mode_ref = new_mode;
+                            }
+                        },
+                        UiMode::BoardEdit { cursor, color } => {
+                            if let Some(new_mode) = crate::ui_mode_actions::handle_board_edit_input(&key, cursor, color, game) {
                                 *mode_ref = new_mode;
                             }
                         },
                     }
+                    // With autosave on, every finished edit is written straight away; only failures are reported
+                    if game.autosave && game.is_dirty() && game.original_sgf_path.is_some() {
+                        if let Err(e) = game.save_to_file() {
+                            game.status = Some(Status::Error(format!("Autosave failed: {}", e)));
+                        }
+                    }
                 },
                 _ => {}
             }
@@ -172,9 +390,64 @@ pub fn run_ui(game: &mut GoGame) -> io::Result<()> {
     restore_terminal(&mut terminal)
 }
 
+/// One line describing a game for the game picker: players, date and result
+fn game_summary(root: &SgfNode) -> String {
+    let field = |key: &str| root.property(key).filter(|v| !v.trim().is_empty()).unwrap_or("?");
+    format!("{} vs {} | {} | {}", field("PB"), field("PW"), field("DT"), field("RE"))
+}
+
+/// Pane showing the current node's whole comment, wrapped and scrolled down by `scroll` rows
+fn render_comment<'a>(game: &'a GoGame, area: Rect, scroll: &mut usize) -> Paragraph<'a> {
+    let comment = game.current_node().comment.as_deref().unwrap_or_default();
+    let rows = wrap_text(comment, area.width.saturating_sub(2) as usize);
+    let visible = area.height.saturating_sub(2) as usize;
+    *scroll = (*scroll).min(rows.len().saturating_sub(visible));
+    let title = if rows.len() > visible {
+        format!("Comment ({}-{} of {} lines, PgUp/PgDn)", *scroll + 1, (*scroll + visible).min(rows.len()), rows.len())
+    } else {
+        String::from("Comment")
+    };
+    let lines: Vec<Line> = rows.into_iter().skip(*scroll).map(|(_, text)| Line::raw(text)).collect();
+    Paragraph::new(lines).block(Block::default().title(title).borders(Borders::ALL))
+}
+
+/// File name, with a marker while there are unsaved edits
+fn render_title(game: &GoGame) -> Paragraph<'_> {
+    let name = game.original_sgf_path.as_deref().unwrap_or("[No Name]");
+    let mut spans = vec![Span::styled(format!("gocat - {}", name), Style::default().add_modifier(Modifier::BOLD))];
+    if game.is_dirty() {
+        spans.push(Span::styled(" [+]", Style::default().fg(Color::Red).add_modifier(Modifier::BOLD)));
+    }
+    if game.read_only {
+        spans.push(Span::styled(" [read-only]", Style::default().fg(Color::Yellow)));
+    }
+    if game.autosave {
+        spans.push(Span::styled(" (autosave)", Style::default().fg(Color::DarkGray)));
+    }
+    Paragraph::new(Line::from(spans))
+}
+
+/// Command being typed, or the latest message, or the keys of the board edit mode
+fn render_status_line<'a>(game: &'a GoGame, mode: &'a UiMode) -> Paragraph<'a> {
+    let line = match (mode, &game.status) {
+        (UiMode::CommandInput { input }, _) => Line::raw(format!(":{}", input)),
+        (_, Some(Status::Info(message))) => Line::styled(message.as_str(), Style::default().fg(Color::Yellow)),
+        (_, Some(Status::Error(message))) => Line::styled(message.as_str(), Style::default().fg(Color::Red)),
+        (UiMode::BoardEdit { cursor: (x, y), color }, None) => Line::styled(
+            format!(
+                "Board edit [{}]: Enter play {} (Tab switches), t/o/s/x/d/e markup, L label, B/W/E setup, Esc done",
+                format_point_input(*x, *y),
+                if *color == Player::Black { "Black" } else { "White" },
+            ),
+            Style::default().fg(Color::Cyan),
+        ),
+        (_, None) => Line::raw(""),
+    };
+    Paragraph::new(line)
+}
+
 // Helper to center a popup
 fn centered_rect(percent_x: u16, percent_y: u16, r: ratatui::layout::Rect) -> ratatui::layout::Rect {
-    use ratatui::layout::{Rect};
     let popup_layout = ratatui::layout::Layout::default()
         .direction(ratatui::layout::Direction::Vertical)
         .constraints([
@@ -195,83 +468,139 @@ fn centered_rect(percent_x: u16, percent_y: u16, r: ratatui::layout::Rect) -> ra
     horizontal_layout[1]
 }
 
-fn render_board(game: &GoGame) -> Paragraph<'_> {
+/// The board with the current position and markup, and the board edit cursor if there is one
+fn render_board(game: &GoGame, cursor: Option<(usize, usize)>) -> Paragraph<'_> {
     use ratatui::style::{Color, Style};
     use ratatui::text::{Span, Line, Text};
-    let size = game.board_size;
-    let mut lines: Vec<Line> = Vec::with_capacity(size + 1);
+    let (width, height) = (game.board_width, game.board_height);
+    let mut lines: Vec<Line> = Vec::with_capacity(height + 1);
     // Determine current move coordinates if available
-    let (cur_x, cur_y) = if game.move_idx > 0 && game.move_idx <= game.moves.len() {
-        let mv = &game.moves[game.move_idx - 1];
-        (mv.x, mv.y)
-    } else {
-        (usize::MAX, usize::MAX)
-    };
+    let (cur_x, cur_y) = game.current_move()
+        .and_then(|mv| mv.point)
+        .unwrap_or((usize::MAX, usize::MAX));
+    // Annotation of the current move, drawn right next to its stone
+    let badge = game.current_node().annotations.move_annotation.map(|(annotation, emphasized)| {
+        let color = match annotation {
+            MoveAnnotation::Bad | MoveAnnotation::Doubtful => Color::LightRed,
+            MoveAnnotation::Tesuji | MoveAnnotation::Interesting => Color::LightGreen,
+        };
+        let glyph = match annotation.badge(emphasized) {
+            "??" => '⁇',
+            "?!" => '⁈',
+            "!?" => '⁉',
+            "!!" => '‼',
+            single => single.chars().next().unwrap_or('?'),
+        };
+        Span::styled(glyph.to_string(), Style::default().fg(color).add_modifier(Modifier::BOLD))
+    });
+    let markup = game.current_markup();
+    let dimmed = game.dimmed_points();
+    // Colour of the line or arrow passing over each point, and the glyph at each arrow head
+    let mut paths: HashMap<(usize, usize), Color> = HashMap::new();
+    for (pairs, color) in [(&markup.lines, Color::LightGreen), (&markup.arrows, Color::LightMagenta)] {
+        for &(from, to) in pairs {
+            for (px, py) in points_between(from, to) {
+                paths.insert((px, py), color);
+            }
+        }
+    }
+    let arrow_heads: Vec<((usize, usize), char)> = markup.arrows
+        .iter()
+        .map(|&(from, to)| (to, arrow_glyph(from, to)))
+        .collect();
     // Top coordinate row
-    let mut top_spans = Vec::with_capacity(size * 2 + 2);
+    let mut top_spans = Vec::with_capacity(width * 2 + 2);
     // Shift left so letters align with lines
     top_spans.push(Span::raw(" "));
-    for x in 0..size {
+    for x in 0..width {
         top_spans.push(Span::raw(" "));
-        let letter = ((b'a' + x as u8) as char).to_string();
+        let letter = coord_to_char(x).to_string();
         top_spans.push(Span::styled(letter, Style::default().fg(Color::Yellow)));
     }
     lines.push(Line::from(top_spans));
     // Board rows with left coordinate
-    for y in 0..size {
-        let mut spans = Vec::with_capacity(size * 2 + 2);
+    for y in 0..height {
+        let mut spans = Vec::with_capacity(width * 2 + 2);
         // Row letter
-        let letter = ((b'a' + y as u8) as char).to_string();
+        let letter = coord_to_char(y).to_string();
         spans.push(Span::styled(format!("{} ", letter), Style::default().fg(Color::Yellow)));
-        for x in 0..size {
-            let triangle_here = game.current_triangles().iter().any(|&(tx, ty)| tx == x && ty == y);
-            let (ch, is_grid, triangle_on_empty) = match game.board[y][x] {
-                Stone::Black => {
-                    if triangle_here {
-                        ('▲', false, false)
-                    } else {
-                        ('●', false, false)
-                    }
-                },
-                Stone::White => {
-                    if triangle_here {
-                        ('△', false, false)
-                    } else {
-                        ('○', false, false)
+        for x in 0..width {
+            let stone = game.board[y][x];
+            let at = |points: &Vec<(usize, usize)>| points.contains(&(x, y));
+            let pick = |on_black: char, other: char| if stone == Stone::Black { on_black } else { other };
+            let label = markup.labels.iter().find(|(p, _)| *p == (x, y)).and_then(|(_, text)| text.chars().next());
+            let head = arrow_heads.iter().find(|(p, _)| *p == (x, y)).map(|&(_, glyph)| glyph);
+            let (ch, mut style) = if let Some(c) = label {
+                let style = match stone {
+                    Stone::Black => Style::default().fg(Color::White).bg(Color::DarkGray),
+                    Stone::White => Style::default().fg(Color::Black).bg(Color::White),
+                    Stone::Empty => Style::default().fg(Color::LightCyan),
+                };
+                (c, style.add_modifier(Modifier::BOLD))
+            } else if at(&markup.triangles) {
+                (pick('▲', '△'), Style::default().fg(Color::Yellow))
+            } else if at(&markup.circles) {
+                (pick('◉', '◎'), Style::default().fg(Color::Cyan))
+            } else if at(&markup.squares) {
+                (pick('■', '□'), Style::default().fg(Color::Magenta))
+            } else if at(&markup.crosses) {
+                (pick('⊗', '×'), Style::default().fg(Color::Green))
+            } else {
+                match stone {
+                    Stone::Black | Stone::White => {
+                        let ch = pick('●', '○');
+                        if let Some(color) = head.map(|_| Color::LightMagenta).or(paths.get(&(x, y)).copied()) {
+                            (ch, Style::default().fg(color))
+                        } else if x == cur_x && y == cur_y {
+                            // Highlight the current move in red
+                            (ch, Style::default().fg(Color::Red))
+                        } else {
+                            (ch, Style::default())
+                        }
                     }
-                },
-                Stone::Empty => {
-                    if triangle_here {
-                        ('△', false, true)
-                    } else {
-                        let grid_ch = match (y, x) {
-                            (0, 0) => '┌',
-                            (0, xx) if xx == size - 1 => '┐',
-                            (yy, 0) if yy == size - 1 => '└',
-                            (yy, xx) if yy == size - 1 && xx == size - 1 => '┘',
-                            (0, _) => '┬',
-                            (_, 0) => '├',
-                            (yy, _) if yy == size - 1 => '┴',
-                            (_, xx) if xx == size - 1 => '┤',
-                            _ => '┼',
-                        };
-                        (grid_ch, true, false)
+                    Stone::Empty => {
+                        if let Some(glyph) = head {
+                            (glyph, Style::default().fg(Color::LightMagenta))
+                        } else if let Some(&color) = paths.get(&(x, y)) {
+                            ('•', Style::default().fg(color))
+                        } else {
+                            let grid_ch = match (y, x) {
+                                (0, 0) => '┌',
+                                (0, xx) if xx == width - 1 => '┐',
+                                (yy, 0) if yy == height - 1 => '└',
+                                (yy, xx) if yy == height - 1 && xx == width - 1 => '┘',
+                                (0, _) => '┬',
+                                (_, 0) => '├',
+                                (yy, _) if yy == height - 1 => '┴',
+                                (_, xx) if xx == width - 1 => '┤',
+                                _ => '┼',
+                            };
+                            (grid_ch, Style::default().fg(Color::Blue))
+                        }
                     }
                 }
             };
-            if is_grid {
-                spans.push(Span::styled(ch.to_string(), Style::default().fg(Color::Blue)));
-            } else if triangle_here {
-                spans.push(Span::styled(ch.to_string(), Style::default().fg(Color::Yellow)));
-            } else if x == cur_x && y == cur_y {
-                // Highlight the current move in red
-                spans.push(Span::styled(ch.to_string(), Style::default().fg(Color::Red)));
-            } else {
-                spans.push(Span::raw(ch.to_string()));
+            if at(&markup.selected) {
+                style = style.add_modifier(Modifier::REVERSED);
+            }
+            if dimmed.contains(&(x, y)) {
+                style = style.fg(Color::DarkGray).add_modifier(Modifier::DIM);
+            }
+            if cursor == Some((x, y)) {
+                style = style.bg(Color::Yellow);
             }
-            if x < size - 1 {
-                // Horizontal line
-                spans.push(Span::styled("─", Style::default().fg(Color::Blue)));
+            spans.push(Span::styled(ch.to_string(), style));
+            let badge_here = badge.as_ref().filter(|_| x == cur_x && y == cur_y);
+            if x < width - 1 {
+                // Horizontal line, or the move's badge after the current move
+                match badge_here {
+                    Some(badge) => spans.push(badge.clone()),
+                    None => spans.push(Span::styled("─", Style::default().fg(Color::Blue))),
+                }
+            } else if let Some(badge) = badge_here.filter(|_| x > 0) {
+                // No room on the right edge, so the badge goes before the stone instead
+                let before = spans.len() - 2;
+                spans[before] = badge.clone();
             }
         }
         lines.push(Line::from(spans));
@@ -283,43 +612,199 @@ fn render_board(game: &GoGame) -> Paragraph<'_> {
 
 
 
+/// Lays out the game tree on a grid: depth runs left to right with two columns per node,
+/// the main continuation stays on its parent's row and every side variation gets a new row.
+struct TreeLayout {
+    cells: Vec<Vec<(char, Style)>>,
+    current: (usize, usize),
+}
+
+impl TreeLayout {
+    fn set(&mut self, row: usize, col: usize, ch: char, style: Style) {
+        if self.cells.len() <= row {
+            self.cells.resize(row + 1, Vec::new());
+        }
+        let cells = &mut self.cells[row];
+        if cells.len() <= col {
+            cells.resize(col + 1, (' ', Style::default()));
+        }
+        cells[col] = (ch, style);
+    }
+
+    /// Places `node` at (`depth`, `row`) together with everything below it.
+    /// `on_line` tells whether the node lies on the line currently being viewed.
+    ///
+    /// The whole main continuation is laid out first; side variations are then added
+    /// from the deepest branch point up, so their connectors never cross other rows.
+    fn place(&mut self, game: &GoGame, mut node: &SgfNode, mut depth: usize, row: usize, mut on_line: bool) {
+        let line_style = Style::default().fg(Color::Yellow);
+        let off_style = Style::default().fg(Color::DarkGray);
+        let mut branches = Vec::new();
+        loop {
+            let glyph = match node.mv.as_ref().map(|mv| &mv.player) {
+                Some(Player::Black) => '●',
+                Some(Player::White) => '○',
+                None => '◇',
+            };
+            let style = if on_line && depth == game.move_idx {
+                self.current = (row, depth * 2);
+                Style::default().fg(Color::Red).add_modifier(Modifier::BOLD)
+            } else if on_line {
+                line_style
+            } else {
+                off_style
+            };
+            self.set(row, depth * 2, glyph, style);
+            if node.children.is_empty() {
+                break;
+            }
+            let chosen = if on_line { game.line.get(depth).copied() } else { None };
+            let connector = if node.children.len() > 1 { '┬' } else { '─' };
+            let main_style = if chosen == Some(0) { line_style } else { off_style };
+            self.set(row, depth * 2 + 1, connector, main_style);
+            if node.children.len() > 1 {
+                branches.push((node, depth, chosen));
+            }
+            on_line = chosen == Some(0);
+            node = &node.children[0];
+            depth += 1;
+        }
+
+        for (node, depth, chosen) in branches.into_iter().rev() {
+            let col = depth * 2 + 1;
+            for (i, child) in node.children.iter().enumerate().skip(1) {
+                let child_row = self.cells.len();
+                let is_chosen = chosen == Some(i);
+                for r in row + 1..=child_row {
+                    let (ch, style) = match self.cells.get(r).and_then(|cells| cells.get(col)) {
+                        Some(&('╰', style)) => ('├', style),
+                        Some(&('│', style)) => ('│', style),
+                        _ if r == child_row => ('╰', off_style),
+                        _ => ('│', off_style),
+                    };
+                    self.set(r, col, ch, if is_chosen { line_style } else { style });
+                }
+                self.place(game, child, depth + 1, child_row, is_chosen);
+            }
+        }
+    }
+}
+
+fn render_game_tree(game: &GoGame, area: Rect) -> Paragraph<'_> {
+    let mut layout = TreeLayout { cells: vec![Vec::new()], current: (0, 0) };
+    layout.place(game, &game.sgf().root, 0, 0, true);
+
+    // Scroll so that the current node stays in view
+    let width = area.width.saturating_sub(2) as usize;
+    let height = area.height.saturating_sub(2) as usize;
+    let (cur_row, cur_col) = layout.current;
+    let first_col = cur_col.saturating_sub(width / 2);
+    let first_row = (cur_row + 1).saturating_sub(height);
+    let lines: Vec<Line> = layout.cells
+        .iter()
+        .skip(first_row)
+        .take(height)
+        .map(|row| {
+            Line::from(row.iter()
+                .skip(first_col)
+                .take(width)
+                .map(|&(ch, style)| Span::styled(ch.to_string(), style))
+                .collect::<Vec<_>>())
+        })
+        .collect();
+    Paragraph::new(Text::from(lines))
+        .block(Block::default().title("Game Tree").borders(Borders::ALL))
+}
+
+/// Points strictly between `from` and `to` on a straight line across the board
+fn points_between(from: (usize, usize), to: (usize, usize)) -> Vec<(usize, usize)> {
+    let (x0, y0) = (from.0 as isize, from.1 as isize);
+    let (dx, dy) = (to.0 as isize - x0, to.1 as isize - y0);
+    let steps = dx.abs().max(dy.abs());
+    (1..steps)
+        .map(|i| {
+            let x = x0 + (dx * i + dx.signum() * steps / 2) / steps;
+            let y = y0 + (dy * i + dy.signum() * steps / 2) / steps;
+            (x as usize, y as usize)
+        })
+        .collect()
+}
+
+/// Arrow pointing from `from` towards `to`, with y growing downwards
+fn arrow_glyph(from: (usize, usize), to: (usize, usize)) -> char {
+    match (to.0.cmp(&from.0), to.1.cmp(&from.1)) {
+        (Ordering::Greater, Ordering::Equal) => '→',
+        (Ordering::Less, Ordering::Equal) => '←',
+        (Ordering::Equal, Ordering::Greater) => '↓',
+        (Ordering::Equal, Ordering::Less) => '↑',
+        (Ordering::Greater, Ordering::Greater) => '↘',
+        (Ordering::Less, Ordering::Greater) => '↙',
+        (Ordering::Greater, Ordering::Less) => '↗',
+        (Ordering::Less, Ordering::Less) => '↖',
+        (Ordering::Equal, Ordering::Equal) => '•',
+    }
+}
+
 fn render_metadata(game: &GoGame) -> Paragraph<'_> {
     let move_num = game.move_idx;
     let player = match game.current_player() {
         crate::sgf_parser::Player::Black => "Black",
         crate::sgf_parser::Player::White => "White",
     };
-    let total_moves = game.moves.len();
+    let total_moves = game.line_len();
+    let (variation_idx, variation_count) = game.variation_at(move_num);
+    let variation_str = if variation_count > 1 {
+        format!(" | Variation: {} / {}", variation_idx + 1, variation_count)
+    } else {
+        String::new()
+    };
+    let game_str = if game.games.len() > 1 {
+        format!(" | Game: {} / {}", game.game_idx + 1, game.games.len())
+    } else {
+        String::new()
+    };
     // Show coordinates of the current move if available, on the first line
     let mut coord_str = String::new();
-    let mut comment_str = String::from("Comment: N/A\n");
     let mut label_str = String::from("Labels: N/A\n");
-    if move_num > 0 && move_num <= game.moves.len() {
-        let mv = &game.moves[move_num - 1];
-        let coord = format!("{}{}", (b'a' + mv.y as u8) as char, (b'a' + mv.x as u8) as char);
-        coord_str = format!(" [{}]", coord);
-        if let Some(comment) = &mv.comment {
-            if !comment.trim().is_empty() {
-                // Split comment and labels at first underscore
-                if let Some(underscore_pos) = comment.find('_') {
-                    let comment_part = &comment[..underscore_pos];
-                    let labels_part = &comment[underscore_pos + 1..];
-                    
-                    if !comment_part.trim().is_empty() {
-                        comment_str = format!("Comment: {}\n", comment_part);
-                    }
-                    
-                    if !labels_part.trim().is_empty() {
-                        let labels = labels_part.replace('_', ", ");
-                        label_str = format!("Labels: {}\n", labels);
-                    }
-                } else {
-                    // No underscore found, treat entire string as comment
-                    comment_str = format!("Comment: {}\n", comment);
-                }
-            }
+    let node = game.current_node();
+    if let Some(mv) = &node.mv {
+        coord_str = match mv.point {
+            Some((x, y)) => format!(" [{}]", format_point_input(x, y)),
+            None => String::from(" [Pass]"),
+        };
+    } else if !node.setup.is_empty() {
+        coord_str = String::from(" [Setup]");
+    }
+    let annotations = &node.annotations;
+    if let Some((annotation, emphasized)) = annotations.move_annotation {
+        coord_str.push(' ');
+        coord_str.push_str(annotation.badge(emphasized));
+    }
+    let mut judgement = Vec::new();
+    if let Some((annotation, emphasized)) = annotations.position {
+        judgement.push(if emphasized { format!("Very {}", annotation.name().to_lowercase()) } else { annotation.name().to_string() });
+    }
+    if let Some(emphasized) = annotations.hotspot {
+        judgement.push(String::from(if emphasized { "Important hotspot" } else { "Hotspot" }));
+    }
+    if let Some(value) = annotations.value {
+        judgement.push(format!("Value: {:+}", value));
+    }
+    let judgement_str = if judgement.is_empty() {
+        String::new()
+    } else {
+        format!("Position: {}\n", judgement.join(" | "))
+    };
+    if let Some(placement) = game.last_placement {
+        if placement.ko_violation {
+            coord_str.push_str(" (illegal ko recapture)");
+        } else if placement.suicide {
+            coord_str.push_str(" (suicide)");
         }
     }
+    if !node.move_labels.is_empty() {
+        label_str = format!("Labels: {}\n", node.move_labels.join(", "));
+    }
     use ratatui::text::{Span, Line, Text};
     let mut lines = vec![
         Line::from(vec![Span::styled(
@@ -328,13 +813,14 @@ fn render_metadata(game: &GoGame) -> Paragraph<'_> {
         )]),
     ];
     let info_str = format!(
-        "Move: {}{} / {} | Current Player: {}\n{}{}",
-        move_num, coord_str, total_moves, player, comment_str, label_str
+        "Move: {}{} / {}{}{} | Current Player: {}\nCaptures: Black {} | White {}\n{}{}",
+        move_num, coord_str, total_moves, variation_str, game_str, player,
+        game.black_captures, game.white_captures, judgement_str, label_str
     );
     for l in info_str.lines() {
         lines.push(Line::raw(l.to_owned()));
     }
-    for (k, v) in &game.metadata {
+    for (k, values) in &game.sgf().root.properties {
         if k != "FF"
             && k != "AP"
             && k != "GM"
@@ -347,7 +833,7 @@ fn render_metadata(game: &GoGame) -> Paragraph<'_> {
             && k != "RU"
             && k != "TT"
         {
-            lines.push(Line::raw(format!("{}: {}", k, v)));
+            lines.push(Line::raw(format!("{}: {}", k, values.join(", "))));
         }
     }
     Paragraph::new(Text::from(lines))
@@ -360,7 +846,49 @@ fn setup_terminal() -> io::Result<ratatui::Terminal<CrosstermBackend<Stdout>>> {
     let mut stdout = io::stdout();
     crossterm::execute!(stdout, crossterm::terminal::EnterAlternateScreen)?;
     let backend = CrosstermBackend::new(stdout);
-    Ok(ratatui::Terminal::new(backend)?)
+    ratatui::Terminal::new(backend)
+}
+
+/// Suspends the TUI, lets the user edit the current node's comment in $VISUAL or $EDITOR
+/// through a temporary file, then stores the result and takes the screen back
+fn edit_comment_in_editor(terminal: &mut ratatui::Terminal<CrosstermBackend<Stdout>>, game: &mut GoGame) -> io::Result<()> {
+    let comment = game.current_node().comment.clone().unwrap_or_default();
+    restore_terminal(terminal)?;
+    let edited = run_external_editor(&comment);
+    crossterm::terminal::enable_raw_mode()?;
+    crossterm::execute!(terminal.backend_mut(), crossterm::terminal::EnterAlternateScreen)?;
+    terminal.clear()?;
+    match edited {
+        Ok(text) if text != comment => {
+            let new_comment = if text.trim().is_empty() { None } else { Some(text) };
+            game.edit_current_node(|node| node.comment = new_comment);
+        }
+        Ok(_) => {}
+        Err(e) => game.status = Some(Status::Error(format!("Editing the comment failed: {}", e))),
+    }
+    Ok(())
+}
+
+fn run_external_editor(text: &str) -> io::Result<String> {
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .unwrap_or_else(|_| String::from("vi"));
+    // The variable may hold arguments too, e.g. "code --wait"
+    let mut words = editor.split_whitespace();
+    let program = words.next().ok_or_else(|| io::Error::other("$EDITOR is empty"))?;
+    let (path, mut file) = create_new_file(|random| std::env::temp_dir().join(format!("gocat-comment-{:016x}.txt", random)))?;
+    file.write_all(text.as_bytes())?;
+    drop(file);
+    let status = std::process::Command::new(program).args(words).arg(&path).status();
+    let edited = std::fs::read_to_string(&path);
+    let _ = std::fs::remove_file(&path);
+    match status? {
+        status if status.success() => {}
+        status => return Err(io::Error::other(format!("{} exited with {}", program, status))),
+    }
+    // Editors usually end the file with a newline the comment didn't have
+    let edited = edited?;
+    Ok(edited.strip_suffix('\n').unwrap_or(&edited).to_string())
 }
 
 fn restore_terminal(terminal: &mut ratatui::Terminal<CrosstermBackend<Stdout>>) -> io::Result<()> {
@@ -1,42 +1,70 @@
-use crate::game::{GoGame};
+use crate::game::{GoGame, Status};
 use crate::sgf_parser::Player;
-use crossterm::event::{KeyCode, KeyEvent};
-use crate::sgf_parser::sgf_to_string;
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use crate::sgf_parser::{sgf_coords_to_xy, xy_to_sgf_coords, MarkupKind, MoveAnnotation, PointPair, PositionAnnotation, SetupKind, SgfNode};
+use crate::sgf_parser::{validate_game_info, GAME_INFO_PROPERTIES};
+use crate::text_area::TextArea;
 use crate::ui::{UiMode};
 
+/// Reads a point typed in a dialog in SGF order, column letter then row letter, e.g. "dp".
+/// Returns (x, y) if it is on the board.
+fn parse_point_input(input: &str, game: &GoGame) -> Option<(usize, usize)> {
+    let (x, y) = sgf_coords_to_xy(input)?;
+    game.on_board(x, y).then_some((x, y))
+}
+
+/// Formats a point the way dialogs expect it typed, column letter first
+pub fn format_point_input(x: usize, y: usize) -> String {
+    xy_to_sgf_coords(x, y)
+}
+
 pub enum UiAction {
     Continue,
     ChangeMode(UiMode),
+    /// Suspend the TUI and edit the current comment in an external editor
+    EditCommentInEditor,
     Quit,
 }
 
+/// Hotkeys that change the game, which are disabled in read-only mode
+fn is_edit_key(key: &KeyEvent) -> bool {
+    match key.code {
+        KeyCode::Char('r') => key.modifiers.contains(KeyModifiers::CONTROL),
+        KeyCode::Char(c) => "ixumcCltaev?!=*I".contains(c),
+        _ => false,
+    }
+}
+
 pub fn handle_normal_input(key: &KeyEvent, game: &mut GoGame) -> UiAction {
+    if game.read_only && is_edit_key(key) {
+        game.status = Some(Status::Error(String::from("Read-only: editing is disabled")));
+        return UiAction::Continue;
+    }
     match key.code {
         KeyCode::Char('i') => {
-            let color = if game.move_idx > 0 && game.move_idx <= game.moves.len() {
-                match game.moves[game.move_idx - 1].player {
-                    Player::Black => Player::White,
-                    Player::White => Player::Black,
-                }
-            } else {
-                Player::Black
-            };
-            UiAction::ChangeMode(UiMode::InsertMoveInput { input: String::new(), color })
+            UiAction::ChangeMode(UiMode::InsertMoveInput { input: String::new(), color: next_color(game), error: None })
         },
         KeyCode::Char('x') => {
-            if game.move_idx > 0 && game.move_idx <= game.moves.len() {
-                let idx = game.move_idx - 1;
-                game.moves.remove(idx);
-                game.original_sgf.moves.remove(idx);
-                if game.move_idx > 1 {
-                    game.move_idx -= 1;
-                }
-                game.apply_moves(game.move_idx);
-                let _ = game.save_to_file();
+            if game.move_idx > 0 {
+                game.remove_current_node();
             }
             UiAction::Continue
         },
+        KeyCode::Char('r') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+            if !game.redo() {
+                game.status = Some(Status::Info(String::from("Nothing to redo")));
+            }
+            UiAction::Continue
+        },
+        KeyCode::Char('u') => {
+            if !game.undo() {
+                game.status = Some(Status::Info(String::from("Nothing to undo")));
+            }
+            UiAction::Continue
+        },
+        KeyCode::Char('q') if game.is_dirty() => UiAction::ChangeMode(UiMode::ConfirmQuit),
         KeyCode::Char('q') => UiAction::Quit,
+        KeyCode::Char(':') => UiAction::ChangeMode(UiMode::CommandInput { input: String::new() }),
         KeyCode::Char('n') | KeyCode::Right => {
             game.next_move();
             UiAction::Continue
@@ -45,77 +73,94 @@ pub fn handle_normal_input(key: &KeyEvent, game: &mut GoGame) -> UiAction {
             game.prev_move();
             UiAction::Continue
         },
+        KeyCode::Down => {
+            game.switch_variation(true);
+            UiAction::Continue
+        },
+        KeyCode::Up => {
+            game.switch_variation(false);
+            UiAction::Continue
+        },
+        KeyCode::Char('b') => {
+            game.goto_branch_point();
+            UiAction::Continue
+        },
         KeyCode::Char(']') => {
-            if !game.moves.is_empty() && game.move_idx < game.moves.len() {
-                let mut idx = game.move_idx;
-                while idx < game.moves.len() {
-                    if let Some(c) = &game.moves[idx].comment {
-                        if !c.trim().is_empty() {
-                            game.move_idx = idx + 1;
-                            game.apply_moves(game.move_idx);
-                            break;
-                        }
-                    }
-                    idx += 1;
-                }
-            }
+            goto_matching_node(game, true, has_comment);
             UiAction::Continue
         },
         KeyCode::Char('[') => {
-            if !game.moves.is_empty() && game.move_idx > 1 {
-                let mut idx = game.move_idx - 2;
-                loop {
-                    if let Some(c) = &game.moves[idx].comment {
-                        if !c.trim().is_empty() {
-                            game.move_idx = idx + 1;
-                            game.apply_moves(game.move_idx);
-                            break;
-                        }
-                    }
-                    if idx == 0 { break; }
-                    idx -= 1;
-                }
-            }
+            goto_matching_node(game, false, has_comment);
+            UiAction::Continue
+        },
+        KeyCode::Char('}') => {
+            goto_matching_node(game, true, is_bad_move);
+            UiAction::Continue
+        },
+        KeyCode::Char('{') => {
+            goto_matching_node(game, false, is_bad_move);
+            UiAction::Continue
+        },
+        KeyCode::Char('?') => {
+            use MoveAnnotation::*;
+            cycle_move_annotation(game, &[(Bad, false), (Bad, true), (Doubtful, false)]);
+            UiAction::Continue
+        },
+        KeyCode::Char('!') => {
+            use MoveAnnotation::*;
+            cycle_move_annotation(game, &[(Tesuji, false), (Tesuji, true), (Interesting, false)]);
+            UiAction::Continue
+        },
+        KeyCode::Char('=') => {
+            use PositionAnnotation::*;
+            let steps = [(GoodForBlack, false), (GoodForWhite, false), (Even, false), (Unclear, false)];
+            game.edit_current_node(|node| {
+                node.annotations.position = next_in_cycle(node.annotations.position, &steps);
+            });
+            UiAction::Continue
+        },
+        KeyCode::Char('*') => {
+            game.edit_current_node(|node| {
+                node.annotations.hotspot = next_in_cycle(node.annotations.hotspot, &[false, true]);
+            });
             UiAction::Continue
         },
         KeyCode::Char('g') => UiAction::ChangeMode(UiMode::GotoMoveInput { input: String::new() }),
-        KeyCode::Char('m') => UiAction::ChangeMode(UiMode::ModifyMoveInput { input: String::new() }),
+        KeyCode::Char('G') => UiAction::ChangeMode(UiMode::GamePicker { selected: game.game_idx }),
+        KeyCode::Char('I') => {
+            let root = &game.sgf().root;
+            let values = GAME_INFO_PROPERTIES
+                .iter()
+                .map(|(key, _)| root.property(key).unwrap_or_default().to_string())
+                .collect();
+            UiAction::ChangeMode(UiMode::GameInfoEditor { values, selected: 0, error: None })
+        },
+        KeyCode::Char('m') => UiAction::ChangeMode(UiMode::ModifyMoveInput { input: String::new(), error: None }),
         KeyCode::Char('h') => UiAction::ChangeMode(UiMode::HotkeyHelp),
         KeyCode::Char('/') => UiAction::ChangeMode(UiMode::SearchCoordInput { input: String::new() }),
         KeyCode::Char('c') => {
-            if game.move_idx > 0 && game.move_idx <= game.moves.len() {
-                let comment = game.moves[game.move_idx - 1].comment.clone().unwrap_or_default();
-                // Extract only the comment part (before first underscore)
-                let comment_only = comment.split('_').next().unwrap_or("").to_string();
-                UiAction::ChangeMode(UiMode::EditCommentInput { input: comment_only })
-            } else {
-                UiAction::Continue
-            }
+            let comment = game.current_node().comment.clone().unwrap_or_default();
+            UiAction::ChangeMode(UiMode::EditCommentInput { input: TextArea::new(comment) })
         },
+        KeyCode::Char('C') => UiAction::EditCommentInEditor,
         KeyCode::Char('l') => {
-            if game.move_idx > 0 && game.move_idx <= game.moves.len() {
-                let comment = game.moves[game.move_idx - 1].comment.clone().unwrap_or_default();
-                // Extract labels (everything after first underscore)
-                let labels = if let Some(underscore_pos) = comment.find('_') {
-                    comment[underscore_pos + 1..].replace('_', ",")
-                } else {
-                    String::new()
-                };
-                UiAction::ChangeMode(UiMode::EditLabelInput { input: labels })
-            } else {
-                UiAction::Continue
-            }
+            let labels = game.current_node().move_labels.join(",");
+            UiAction::ChangeMode(UiMode::EditLabelInput { input: labels })
         },
         KeyCode::Char('t') => {
-            let input = game.current_triangles()
-                .iter()
-                .map(|(x, y)| {
-                    let c = |v| (b'a' + v as u8) as char;
-                    format!("{}{}", c(*y), c(*x))
-                })
-                .collect::<Vec<_>>()
-                .join(",");
-            UiAction::ChangeMode(UiMode::EditTrianglesInput { input })
+            let input = markup_input(game, MarkupKind::Triangle);
+            UiAction::ChangeMode(UiMode::EditMarkupInput { kind: MarkupKind::Triangle, input })
+        },
+        KeyCode::Char('a') => UiAction::ChangeMode(UiMode::MarkupMenu),
+        KeyCode::Char('v') => {
+            // Start on the current move, or in the middle of the board
+            let cursor = game.current_move()
+                .and_then(|mv| mv.point)
+                .unwrap_or((game.board_width / 2, game.board_height / 2));
+            UiAction::ChangeMode(UiMode::BoardEdit { cursor, color: next_color(game) })
+        },
+        KeyCode::Char('e') => {
+            UiAction::ChangeMode(UiMode::EditSetupInput { kind: SetupKind::Black, inputs: setup_inputs(game) })
         },
         _ => UiAction::Continue
     }
@@ -127,10 +172,7 @@ pub fn handle_goto_move_input(key: &KeyEvent, input: &mut String, game: &mut GoG
         KeyCode::Esc => Some(UiMode::Normal),
         KeyCode::Enter => {
             if let Ok(num) = input.parse::<usize>() {
-                if num <= game.moves.len() {
-                    game.move_idx = num;
-                    game.apply_moves(game.move_idx);
-                }
+                game.goto_move(num);
             }
             Some(UiMode::Normal)
         },
@@ -147,26 +189,26 @@ pub fn handle_goto_move_input(key: &KeyEvent, input: &mut String, game: &mut GoG
 }
 
 // ModifyMoveInput handler
-pub fn handle_modify_move_input(key: &KeyEvent, input: &mut String, game: &mut GoGame) -> Option<UiMode> {
+pub fn handle_modify_move_input(key: &KeyEvent, input: &mut String, error: &mut Option<String>, game: &mut GoGame) -> Option<UiMode> {
     match key.code {
         KeyCode::Esc => Some(UiMode::Normal),
         KeyCode::Enter => {
-            if input.len() == 2 {
-                let y = (input.chars().nth(0).unwrap() as u8).wrapping_sub(b'a') as usize;
-                let x = (input.chars().nth(1).unwrap() as u8).wrapping_sub(b'a') as usize;
-                if x < game.board_size && y < game.board_size && game.move_idx > 0 && game.move_idx <= game.moves.len() {
-                    let idx = game.move_idx - 1;
-                    game.moves[idx].x = x;
-                    game.moves[idx].y = y;
-                    game.original_sgf.moves[idx].x = x;
-                    game.original_sgf.moves[idx].y = y;
-                    game.apply_moves(game.move_idx);
-                    let _ = game.save_to_file();
+            if let Some((x, y)) = parse_point_input(input, game) {
+                if let Some(player) = game.current_move().map(|mv| mv.player.clone()) {
+                    if let Err(e) = game.legality_as_replacement(x, y, &player) {
+                        *error = Some(e.to_string());
+                        return None;
+                    }
+                    game.edit_current_node(|node| {
+                        if let Some(mv) = node.mv.as_mut() {
+                            mv.point = Some((x, y));
+                        }
+                    });
                 }
             }
             Some(UiMode::Normal)
         },
-        KeyCode::Char(c) if c.is_ascii_lowercase() && input.len() < 2 => {
+        KeyCode::Char(c) if c.is_ascii_alphabetic() && input.len() < 2 => {
             input.push(c);
             None
         },
@@ -183,19 +225,17 @@ pub fn handle_search_coord_input(key: &KeyEvent, input: &mut String, game: &mut
     match key.code {
         KeyCode::Esc => Some(UiMode::Normal),
         KeyCode::Enter => {
-            if input.len() == 2 {
-                let y = (input.chars().nth(0).unwrap() as u8).wrapping_sub(b'a') as usize;
-                let x = (input.chars().nth(1).unwrap() as u8).wrapping_sub(b'a') as usize;
-                if x < game.board_size && y < game.board_size {
-                    if let Some(idx) = game.moves.iter().position(|mv| mv.x == x && mv.y == y) {
-                        game.move_idx = idx + 1;
-                        game.apply_moves(game.move_idx);
-                    }
+            if let Some((x, y)) = parse_point_input(input, game) {
+                let target = game.line_nodes()
+                    .iter()
+                    .position(|node| node.mv.as_ref().is_some_and(|mv| mv.point == Some((x, y))));
+                if let Some(depth) = target {
+                    game.goto_move(depth);
                 }
             }
             Some(UiMode::Normal)
         },
-        KeyCode::Char(c) if c.is_ascii_lowercase() && input.len() < 2 => {
+        KeyCode::Char(c) if c.is_ascii_alphabetic() && input.len() < 2 => {
             input.push(c);
             None
         },
@@ -207,81 +247,194 @@ pub fn handle_search_coord_input(key: &KeyEvent, input: &mut String, game: &mut
     }
 }
 
-// EditCommentInput handler
-pub fn handle_edit_comment_input(key: &KeyEvent, input: &mut String, game: &mut GoGame) -> Option<UiMode> {
-    match key.code {
-        KeyCode::Esc => Some(UiMode::Normal),
-        KeyCode::Enter => {
-            if game.move_idx > 0 && game.move_idx <= game.moves.len() {
-                let idx = game.move_idx - 1;
-                let existing_comment = game.moves[idx].comment.clone().unwrap_or_default();
-                // Preserve existing labels (everything after first underscore)
-                let existing_labels = if let Some(underscore_pos) = existing_comment.find('_') {
-                    existing_comment[underscore_pos..].to_string()
-                } else {
-                    String::new()
-                };
-                
-                let new_comment = if input.trim().is_empty() {
-                    if existing_labels.is_empty() { None } else { Some(existing_labels) }
-                } else {
-                    Some(format!("{}{}", input.trim(), existing_labels))
-                };
-                
-                game.moves[idx].comment = new_comment.clone();
-                game.original_sgf.moves[idx].comment = new_comment;
-                let _ = game.save_to_file();
+/// Saves the file, reporting the outcome in the status line. Returns whether it worked.
+fn save_with_status(game: &mut GoGame, path: Option<&str>) -> bool {
+    let result = match path {
+        Some(path) => game.save_as(path),
+        None => game.save_to_file(),
+    };
+    match result {
+        Ok(()) => {
+            let path = game.original_sgf_path.as_deref().unwrap_or_default();
+            game.status = Some(Status::Info(format!("Saved {}", path)));
+            true
+        }
+        Err(e) => {
+            game.status = Some(Status::Error(format!("Save failed: {}", e)));
+            false
+        }
+    }
+}
+
+/// Runs a command typed after ':'
+fn run_command(command: &str, game: &mut GoGame) -> UiAction {
+    let (name, arg) = match command.trim().split_once(char::is_whitespace) {
+        Some((name, arg)) => (name, Some(arg.trim())),
+        None => (command.trim(), None),
+    };
+    match (name, arg) {
+        ("w", None) => {
+            save_with_status(game, None);
+        }
+        ("w" | "saveas", Some(path)) => {
+            save_with_status(game, Some(path));
+        }
+        ("saveas", None) => game.status = Some(Status::Error(String::from("Usage: :saveas <path>"))),
+        ("q", None) if game.is_dirty() => {
+            game.status = Some(Status::Error(String::from("Unsaved changes: :w to save them or :q! to discard them")));
+        }
+        ("q", None) | ("q!", None) => return UiAction::Quit,
+        ("wq" | "x", None) => {
+            if save_with_status(game, None) {
+                return UiAction::Quit;
             }
-            Some(UiMode::Normal)
-        },
+        }
+        ("", None) => {}
+        _ => game.status = Some(Status::Error(format!("Unknown command: {}", command.trim()))),
+    }
+    UiAction::ChangeMode(UiMode::Normal)
+}
+
+// CommandInput handler
+pub fn handle_command_input(key: &KeyEvent, input: &mut String, game: &mut GoGame) -> UiAction {
+    match key.code {
+        KeyCode::Esc => UiAction::ChangeMode(UiMode::Normal),
+        KeyCode::Enter => run_command(input, game),
+        KeyCode::Backspace if input.is_empty() => UiAction::ChangeMode(UiMode::Normal),
         KeyCode::Backspace => {
             input.pop();
-            None
+            UiAction::Continue
         },
         KeyCode::Char(c) => {
-            // Block underscores in comment input
-            if c != '_' {
-                input.push(c);
+            input.push(c);
+            UiAction::Continue
+        },
+        _ => UiAction::Continue
+    }
+}
+
+// ConfirmQuit handler
+pub fn handle_confirm_quit_input(key: &KeyEvent, game: &mut GoGame) -> UiAction {
+    match key.code {
+        KeyCode::Char('y') | KeyCode::Char('Y') => {
+            if save_with_status(game, None) {
+                UiAction::Quit
+            } else {
+                UiAction::ChangeMode(UiMode::Normal)
+            }
+        },
+        KeyCode::Char('n') | KeyCode::Char('N') => UiAction::Quit,
+        KeyCode::Esc | KeyCode::Char('c') => UiAction::ChangeMode(UiMode::Normal),
+        _ => UiAction::Continue
+    }
+}
+
+// GamePicker handler
+pub fn handle_game_picker_input(key: &KeyEvent, selected: &mut usize, game: &mut GoGame) -> Option<UiMode> {
+    match key.code {
+        KeyCode::Esc => Some(UiMode::Normal),
+        KeyCode::Up | KeyCode::Char('k') => {
+            *selected = selected.saturating_sub(1);
+            None
+        },
+        KeyCode::Down | KeyCode::Char('j') => {
+            if *selected + 1 < game.games.len() {
+                *selected += 1;
             }
             None
         },
+        KeyCode::Enter => {
+            if *selected != game.game_idx {
+                game.select_game(*selected);
+            }
+            Some(UiMode::Normal)
+        },
         _ => None
     }
 }
 
+// GameInfoEditor handler
+pub fn handle_game_info_input(key: &KeyEvent, values: &mut [String], selected: &mut usize, error: &mut Option<String>, game: &mut GoGame) -> Option<UiMode> {
+    match key.code {
+        KeyCode::Esc => return Some(UiMode::Normal),
+        KeyCode::Up | KeyCode::BackTab => *selected = selected.checked_sub(1).unwrap_or(values.len() - 1),
+        KeyCode::Down | KeyCode::Tab => *selected = (*selected + 1) % values.len(),
+        KeyCode::Enter => {
+            // Nothing is saved until every field is valid; empty fields remove the property
+            for (i, ((key, name), value)) in GAME_INFO_PROPERTIES.iter().zip(values.iter()).enumerate() {
+                if value.trim().is_empty() {
+                    continue;
+                }
+                if let Err(e) = validate_game_info(key, value) {
+                    *selected = i;
+                    *error = Some(format!("{}: {}", name, e));
+                    return None;
+                }
+            }
+            game.edit_node(0, |root| {
+                for ((key, _), value) in GAME_INFO_PROPERTIES.iter().zip(values.iter()) {
+                    let value = value.trim();
+                    root.set_property(key, (!value.is_empty()).then(|| value.to_string()));
+                }
+            });
+            return Some(UiMode::Normal);
+        },
+        KeyCode::Char(c) => values[*selected].push(c),
+        KeyCode::Backspace => {
+            values[*selected].pop();
+        },
+        _ => {}
+    }
+    *error = None;
+    None
+}
+
+// EditCommentInput handler
+pub fn handle_edit_comment_input(key: &KeyEvent, input: &mut TextArea, game: &mut GoGame) -> Option<UiMode> {
+    match key.code {
+        KeyCode::Esc => return Some(UiMode::Normal),
+        KeyCode::Char('s') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+            let text = input.text.trim_end();
+            let new_comment = if text.trim().is_empty() { None } else { Some(text.to_string()) };
+            game.edit_current_node(|node| node.comment = new_comment);
+            return Some(UiMode::Normal);
+        },
+        KeyCode::Enter => input.insert('\n'),
+        KeyCode::Char(c) => input.insert(c),
+        KeyCode::Backspace => input.backspace(),
+        KeyCode::Delete => input.delete(),
+        KeyCode::Left => input.left(),
+        KeyCode::Right => input.right(),
+        KeyCode::Up => input.up(),
+        KeyCode::Down => input.down(),
+        KeyCode::Home => input.home(),
+        KeyCode::End => input.end(),
+        _ => {}
+    }
+    None
+}
+
 // InsertMoveInput handler
-pub fn handle_insert_move_input(key: &KeyEvent, input: &mut String, color: &mut Player, game: &mut GoGame) -> Option<UiMode> {
+pub fn handle_insert_move_input(key: &KeyEvent, input: &mut String, color: &mut Player, error: &mut Option<String>, game: &mut GoGame) -> Option<UiMode> {
     match key.code {
         KeyCode::Esc => Some(UiMode::Normal),
         KeyCode::Tab => {
-            *color = match color {
-                Player::Black => Player::White,
-                Player::White => Player::Black,
-            };
+            *color = color.opponent();
             None
         },
         KeyCode::Enter => {
-            if input.len() == 2 {
-                let y = (input.chars().nth(0).unwrap() as u8).wrapping_sub(b'a') as usize;
-                let x = (input.chars().nth(1).unwrap() as u8).wrapping_sub(b'a') as usize;
-                if x < game.board_size && y < game.board_size {
-                    let new_move = crate::sgf_parser::Move {
-                        player: color.clone(),
-                        x,
-                        y,
-                        comment: None,
-                        triangles: vec![],
-                    };
-                    game.moves.insert(game.move_idx, new_move.clone());
-                    game.original_sgf.moves.insert(game.move_idx, new_move);
-                    game.move_idx += 1;
-                    game.apply_moves(game.move_idx);
-                    let _ = game.save_to_file();
+            if input.is_empty() {
+                game.insert_move(crate::sgf_parser::Move { player: color.clone(), point: None });
+            } else if let Some((x, y)) = parse_point_input(input, game) {
+                if let Err(e) = game.legality(x, y, color) {
+                    *error = Some(e.to_string());
+                    return None;
                 }
+                game.insert_move(crate::sgf_parser::Move { player: color.clone(), point: Some((x, y)) });
             }
             Some(UiMode::Normal)
         },
-        KeyCode::Char(c) if c.is_ascii_lowercase() && input.len() < 2 => {
+        KeyCode::Char(c) if c.is_ascii_alphabetic() && input.len() < 2 => {
             input.push(c);
             None
         },
@@ -293,38 +446,131 @@ pub fn handle_insert_move_input(key: &KeyEvent, input: &mut String, color: &mut
     }
 }
 
+/// Colour of a move played after the current node: the one its PL names, otherwise the
+/// opponent of the last move so far. Before any move White starts handicap games.
+fn next_color(game: &GoGame) -> Player {
+    match game.current_node().property("PL").map(|v| v.trim().to_ascii_uppercase()).as_deref() {
+        Some("B") => return Player::Black,
+        Some("W") => return Player::White,
+        _ => {}
+    }
+    let last_move = game.line_nodes()
+        .into_iter()
+        .take(game.move_idx + 1)
+        .rev()
+        .find_map(|node| node.mv.as_ref().map(|mv| mv.player.clone()));
+    match last_move {
+        Some(Player::Black) => Player::White,
+        Some(Player::White) => Player::Black,
+        None if game.sgf().root.property("HA").and_then(|v| v.trim().parse::<usize>().ok()).is_some_and(|ha| ha > 1) => Player::White,
+        None => Player::Black,
+    }
+}
+
+/// Adds `point` to `points`, or removes it if it is already there
+fn toggle_point(points: &mut Vec<(usize, usize)>, point: (usize, usize)) {
+    match points.iter().position(|&p| p == point) {
+        Some(i) => {
+            points.remove(i);
+        },
+        None => points.push(point),
+    }
+}
+
+fn toggle_markup(game: &mut GoGame, kind: MarkupKind, point: (usize, usize)) {
+    // Dimming starts from what is shown, which may come from a DD on an earlier node
+    let shown = game.dimmed_points();
+    let mut inherited = match game.move_idx {
+        0 => Vec::new(),
+        depth => game.dimmed_points_at(depth - 1),
+    };
+    inherited.sort();
+    game.edit_current_node(|node| {
+        let markup = &mut node.markup;
+        let points = match kind {
+            MarkupKind::Triangle => &mut markup.triangles,
+            MarkupKind::Circle => &mut markup.circles,
+            MarkupKind::Square => &mut markup.squares,
+            MarkupKind::Cross => &mut markup.crosses,
+            MarkupKind::Selected => &mut markup.selected,
+            MarkupKind::Dim => markup.dimmed.get_or_insert(shown),
+            MarkupKind::Label | MarkupKind::Arrow | MarkupKind::Line => return,
+        };
+        toggle_point(points, point);
+        // A DD that dims just what is inherited anyway isn't needed. Dropping it also keeps an
+        // emptied list from being saved as DD[], which would undim everything.
+        if let (MarkupKind::Dim, Some(dimmed)) = (kind, &markup.dimmed) {
+            let mut dimmed = dimmed.clone();
+            dimmed.sort();
+            if dimmed == inherited {
+                markup.dimmed = None;
+            }
+        }
+    });
+}
+
+/// Removes the label at `point`, or puts the first capital letter not used yet on this node there
+fn toggle_label(game: &mut GoGame, point: (usize, usize)) {
+    let labels = &game.current_markup().labels;
+    if labels.iter().any(|(p, _)| *p == point) {
+        game.edit_current_node(|node| node.markup.labels.retain(|(p, _)| *p != point));
+    } else if let Some(letter) = ('A'..='Z').find(|c| !labels.iter().any(|(_, text)| *text == c.to_string())) {
+        game.edit_current_node(|node| node.markup.labels.push((point, letter.to_string())));
+    }
+}
+
+fn toggle_setup(game: &mut GoGame, kind: SetupKind, point: (usize, usize)) {
+    game.edit_current_node(|node| {
+        let mut points = node.setup.points(kind).clone();
+        toggle_point(&mut points, point);
+        node.setup.set_points(kind, points);
+    });
+}
+
+// BoardEdit handler
+pub fn handle_board_edit_input(key: &KeyEvent, cursor: &mut (usize, usize), color: &mut Player, game: &mut GoGame) -> Option<UiMode> {
+    let (x, y) = *cursor;
+    match key.code {
+        KeyCode::Esc | KeyCode::Char('v') | KeyCode::Char('q') => return Some(UiMode::Normal),
+        KeyCode::Left | KeyCode::Char('h') => cursor.0 = x.saturating_sub(1),
+        KeyCode::Right | KeyCode::Char('l') => cursor.0 = (x + 1).min(game.board_width - 1),
+        KeyCode::Up | KeyCode::Char('k') => cursor.1 = y.saturating_sub(1),
+        KeyCode::Down | KeyCode::Char('j') => cursor.1 = (y + 1).min(game.board_height - 1),
+        KeyCode::Tab => *color = color.opponent(),
+        KeyCode::Enter | KeyCode::Char(' ') => {
+            match game.legality(x, y, color) {
+                Ok(()) => {
+                    game.play_move(crate::sgf_parser::Move { player: color.clone(), point: Some((x, y)) });
+                    *color = next_color(game);
+                },
+                Err(e) => game.status = Some(Status::Error(e.to_string())),
+            }
+        },
+        KeyCode::Char('L') => toggle_label(game, (x, y)),
+        KeyCode::Char('B') => toggle_setup(game, SetupKind::Black, (x, y)),
+        KeyCode::Char('W') => toggle_setup(game, SetupKind::White, (x, y)),
+        KeyCode::Char('E') => toggle_setup(game, SetupKind::Empty, (x, y)),
+        KeyCode::Char(c) => {
+            if let Some(&(_, kind)) = BOARD_EDIT_MARKUP_KEYS.iter().find(|(k, _)| *k == c) {
+                toggle_markup(game, kind, (x, y));
+            }
+        },
+        _ => {}
+    }
+    None
+}
+
 // EditLabelInput handler
 pub fn handle_edit_label_input(key: &KeyEvent, input: &mut String, game: &mut GoGame) -> Option<UiMode> {
     match key.code {
         KeyCode::Esc => Some(UiMode::Normal),
         KeyCode::Enter => {
-            if game.move_idx > 0 && game.move_idx <= game.moves.len() {
-                let idx = game.move_idx - 1;
-                let existing_comment = game.moves[idx].comment.clone().unwrap_or_default();
-                // Extract existing comment part (before first underscore)
-                let existing_comment_part = existing_comment.split('_').next().unwrap_or("").to_string();
-                
-                let new_comment = if input.trim().is_empty() {
-                    if existing_comment_part.is_empty() { None } else { Some(existing_comment_part) }
-                } else {
-                    // Convert comma-separated labels to underscore-separated
-                    let labels = input.split(',')
-                        .map(|s| s.trim())
-                        .filter(|s| !s.is_empty())
-                        .collect::<Vec<_>>()
-                        .join("_");
-                    
-                    if existing_comment_part.is_empty() {
-                        Some(format!("_{}", labels))
-                    } else {
-                        Some(format!("{}_{}", existing_comment_part, labels))
-                    }
-                };
-                
-                game.moves[idx].comment = new_comment.clone();
-                game.original_sgf.moves[idx].comment = new_comment;
-                let _ = game.save_to_file();
-            }
+            let labels = input.split(',')
+                .map(|s| s.trim())
+                .filter(|s| !s.is_empty())
+                .map(String::from)
+                .collect();
+            game.edit_current_node(|node| node.move_labels = labels);
             Some(UiMode::Normal)
         },
         KeyCode::Backspace => {
@@ -339,34 +585,206 @@ pub fn handle_edit_label_input(key: &KeyEvent, input: &mut String, game: &mut Go
     }
 }
 
-pub fn handle_edit_triangles_input(key: &KeyEvent, input: &mut String, game: &mut GoGame) -> Option<UiMode> {
+fn has_comment(node: &SgfNode) -> bool {
+    node.comment.as_ref().is_some_and(|c| !c.trim().is_empty())
+}
+
+fn is_bad_move(node: &SgfNode) -> bool {
+    matches!(node.annotations.move_annotation, Some((MoveAnnotation::Bad, _)))
+}
+
+/// Jumps to the next (or previous) node of the current line that `matches`
+fn goto_matching_node(game: &mut GoGame, forward: bool, matches: fn(&SgfNode) -> bool) {
+    let nodes = game.line_nodes();
+    let target = if forward {
+        (game.move_idx + 1..nodes.len()).find(|&depth| matches(nodes[depth]))
+    } else {
+        (0..game.move_idx).rev().find(|&depth| matches(nodes[depth]))
+    };
+    if let Some(depth) = target {
+        game.goto_move(depth);
+    }
+}
+
+/// The step after `current` in `steps`, wrapping around through `None`
+fn next_in_cycle<T: Copy + PartialEq>(current: Option<T>, steps: &[T]) -> Option<T> {
+    match current.and_then(|c| steps.iter().position(|&s| s == c)) {
+        Some(i) => steps.get(i + 1).copied(),
+        None => steps.first().copied(),
+    }
+}
+
+/// Moves the current move's annotation one step along `steps`; nodes without a move are left alone
+fn cycle_move_annotation(game: &mut GoGame, steps: &[(MoveAnnotation, bool)]) {
+    if game.current_move().is_none() {
+        return;
+    }
+    game.edit_current_node(|node| {
+        node.annotations.move_annotation = next_in_cycle(node.annotations.move_annotation, steps);
+    });
+}
+
+/// Current node's setup points of each kind, in the form the edit dialog takes them
+fn setup_inputs(game: &GoGame) -> [String; 3] {
+    SetupKind::ALL.map(|kind| {
+        game.current_node().setup.points(kind)
+            .iter()
+            .map(|&(x, y)| format_point_input(x, y))
+            .collect::<Vec<_>>()
+            .join(",")
+    })
+}
+
+fn setup_points(input: &str, game: &GoGame) -> Vec<(usize, usize)> {
+    input.split(',').filter_map(|s| parse_point_input(s.trim(), game)).collect()
+}
+
+// EditSetupInput handler. Nothing changes until Enter, so Esc cancels all three lists.
+pub fn handle_edit_setup_input(key: &KeyEvent, kind: &mut SetupKind, inputs: &mut [String; 3], game: &mut GoGame) -> Option<UiMode> {
+    let input = &mut inputs[*kind as usize];
     match key.code {
         KeyCode::Esc => return Some(UiMode::Normal),
-        KeyCode::Enter => {
-            // Parse comma-separated coords
-            let coords = input.split(',').filter_map(|s| {
-                let s = s.trim();
-                if s.len() == 2 {
-                    let y = (s.chars().nth(0).unwrap() as u8).wrapping_sub(b'a') as usize;
-                    let x = (s.chars().nth(1).unwrap() as u8).wrapping_sub(b'a') as usize;
-                    Some((x, y))
-                } else {
-                    None
-                }
-            }).collect::<Vec<_>>();
-            if let Some(tris) = game.current_triangles_mut() {
-                tris.clear();
-                tris.extend(&coords);
-            }
-            if let Some(tris) = game.original_sgf.moves.get_mut(game.move_idx.saturating_sub(1)) {
-                tris.triangles.clear();
-                tris.triangles.extend(&coords);
+        KeyCode::Tab => {
+            // A point can only be in one list, so the points typed here leave the other two
+            let points = setup_points(input, game);
+            for other in SetupKind::ALL.into_iter().filter(|other| other != kind) {
+                inputs[other as usize] = setup_points(&inputs[other as usize], game)
+                    .into_iter()
+                    .filter(|point| !points.contains(point))
+                    .map(|(x, y)| format_point_input(x, y))
+                    .collect::<Vec<_>>()
+                    .join(",");
             }
-            if let Ok(sgf_str) = sgf_to_string(&game.original_sgf) {
-                if let Some(path) = game.original_sgf_path.as_deref() {
-                    let _ = std::fs::write(path, sgf_str);
+            *kind = match kind {
+                SetupKind::Black => SetupKind::White,
+                SetupKind::White => SetupKind::Empty,
+                SetupKind::Empty => SetupKind::Black,
+            };
+        },
+        KeyCode::Enter => {
+            let lists = inputs.each_ref().map(|input| setup_points(input, game));
+            // The kind being edited goes last, so its points win over the others
+            let order = SetupKind::ALL.into_iter().filter(|other| other != kind).chain([*kind]);
+            game.edit_current_node(|node| {
+                for kind in order {
+                    node.setup.set_points(kind, lists[kind as usize].clone());
                 }
-            }
+            });
+            return Some(UiMode::Normal);
+        },
+        KeyCode::Char(c) => {
+            input.push(c);
+        },
+        KeyCode::Backspace => {
+            input.pop();
+        },
+        _ => {}
+    }
+    None
+}
+
+/// Keys of the markup menu and the markup kind each one edits
+pub const MARKUP_MENU_KEYS: [(char, MarkupKind); 9] = [
+    ('t', MarkupKind::Triangle),
+    ('o', MarkupKind::Circle),
+    ('s', MarkupKind::Square),
+    ('x', MarkupKind::Cross),
+    ('l', MarkupKind::Label),
+    ('a', MarkupKind::Arrow),
+    ('n', MarkupKind::Line),
+    ('d', MarkupKind::Dim),
+    ('e', MarkupKind::Selected),
+];
+
+/// Markup the board edit mode toggles at the cursor. Same letters as the markup menu,
+/// except that `l` moves the cursor there, so labels go on `L`.
+const BOARD_EDIT_MARKUP_KEYS: [(char, MarkupKind); 6] = [
+    ('t', MarkupKind::Triangle),
+    ('o', MarkupKind::Circle),
+    ('s', MarkupKind::Square),
+    ('x', MarkupKind::Cross),
+    ('d', MarkupKind::Dim),
+    ('e', MarkupKind::Selected),
+];
+
+// MarkupMenu handler
+pub fn handle_markup_menu_input(key: &KeyEvent, game: &mut GoGame) -> Option<UiMode> {
+    match key.code {
+        KeyCode::Esc => Some(UiMode::Normal),
+        KeyCode::Char(c) => {
+            let (_, kind) = *MARKUP_MENU_KEYS.iter().find(|(k, _)| *k == c)?;
+            Some(UiMode::EditMarkupInput { kind, input: markup_input(game, kind) })
+        },
+        _ => None
+    }
+}
+
+/// Current node's markup of `kind` in the form the edit dialog takes it
+fn markup_input(game: &GoGame, kind: MarkupKind) -> String {
+    let markup = game.current_markup();
+    let pair = |&((x1, y1), (x2, y2)): &PointPair| {
+        format!("{}-{}", format_point_input(x1, y1), format_point_input(x2, y2))
+    };
+    let items: Vec<String> = match kind {
+        MarkupKind::Label => markup.labels
+            .iter()
+            .map(|((x, y), text)| format!("{}:{}", format_point_input(*x, *y), text))
+            .collect(),
+        MarkupKind::Arrow => markup.arrows.iter().map(pair).collect(),
+        MarkupKind::Line => markup.lines.iter().map(pair).collect(),
+        // DD[] undims everything, which the dialog writes as "-"
+        MarkupKind::Dim if markup.dimmed.as_ref().is_some_and(Vec::is_empty) => vec![String::from("-")],
+        _ => markup.points(kind)
+            .map(|points| points.iter().map(|&(x, y)| format_point_input(x, y)).collect())
+            .unwrap_or_default(),
+    };
+    items.join(",")
+}
+
+/// Replaces the current node's markup of `kind` with what was typed in the edit dialog
+fn apply_markup_input(game: &mut GoGame, kind: MarkupKind, input: &str) {
+    let items: Vec<&str> = input.split(',').map(str::trim).filter(|s| !s.is_empty()).collect();
+    let points: Vec<(usize, usize)> = items.iter().filter_map(|s| parse_point_input(s, game)).collect();
+    let pairs: Vec<PointPair> = items
+        .iter()
+        .filter_map(|s| {
+            let (from, to) = s.split_once('-')?;
+            Some((parse_point_input(from.trim(), game)?, parse_point_input(to.trim(), game)?))
+        })
+        .collect();
+    let labels: Vec<((usize, usize), String)> = items
+        .iter()
+        .filter_map(|s| {
+            let (point, text) = s.split_once(':')?;
+            Some((parse_point_input(point.trim(), game)?, text.trim().to_string()))
+        })
+        .collect();
+    game.edit_current_node(|node| {
+        let markup = &mut node.markup;
+        match kind {
+            MarkupKind::Triangle => markup.triangles = points,
+            MarkupKind::Circle => markup.circles = points,
+            MarkupKind::Square => markup.squares = points,
+            MarkupKind::Cross => markup.crosses = points,
+            MarkupKind::Selected => markup.selected = points,
+            MarkupKind::Dim => markup.dimmed = match items.as_slice() {
+                // Nothing leaves the dimming from earlier nodes in effect, "-" cancels it
+                [] => None,
+                ["-"] => Some(Vec::new()),
+                _ => Some(points),
+            },
+            MarkupKind::Label => markup.labels = labels,
+            MarkupKind::Arrow => markup.arrows = pairs,
+            MarkupKind::Line => markup.lines = pairs,
+        }
+    });
+}
+
+pub fn handle_edit_markup_input(key: &KeyEvent, kind: MarkupKind, input: &mut String, game: &mut GoGame) -> Option<UiMode> {
+    match key.code {
+        KeyCode::Esc => return Some(UiMode::Normal),
+        KeyCode::Enter => {
+            apply_markup_input(game, kind, input);
             return Some(UiMode::Normal);
         },
         KeyCode::Char(c) => {